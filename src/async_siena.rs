@@ -0,0 +1,274 @@
+use crate::query::Query;
+use crate::siena::{self, Record, RecordData, Siena, SienaError, StoreProvider};
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// The asynchronous counterpart of `StoreProvider`, for providers that talk
+// to disk or network without blocking the async runtime.
+pub trait AsyncStoreProvider: Send + Sync {
+    fn retrieve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Vec<Record>>;
+
+    // Execute the `query` natively, see `StoreProvider::query`.
+    fn query<'a>(&'a self, _query: &'a Query) -> BoxFuture<'a, Option<Vec<Record>>> {
+        Box::pin(async { None })
+    }
+
+    fn set<'a>(
+        &'a self,
+        records: Vec<Record>,
+        data: Vec<(&'a str, &'a RecordData)>,
+    ) -> BoxFuture<'a, Result<Vec<Record>, SienaError>>;
+    fn delete(&self, records: Vec<Record>) -> BoxFuture<'_, Result<(), SienaError>>;
+
+    // Write records that must not exist yet, see `StoreProvider::set_new`.
+    fn set_new<'a>(
+        &'a self,
+        records: Vec<Record>,
+        data: Vec<(&'a str, &'a RecordData)>,
+    ) -> BoxFuture<'a, Result<Vec<Record>, SienaError>> {
+        Box::pin(async move {
+            for record in &records {
+                if self
+                    .retrieve(&record.collection)
+                    .await
+                    .iter()
+                    .any(|r| r.id == record.id)
+                {
+                    return Err(SienaError::Exists(record.id.clone()));
+                }
+            }
+
+            self.set(records, data).await
+        })
+    }
+}
+
+impl Debug for dyn AsyncStoreProvider {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "AsyncStoreProvider")
+    }
+}
+
+// Wraps a synchronous `StoreProvider`, like `LocalProvider`, into an
+// `AsyncStoreProvider` by running its methods on Tokio's blocking thread
+// pool. It must therefore be used from within a Tokio runtime.
+pub struct BlockingProvider<P> {
+    inner: Arc<P>,
+}
+
+impl<P> BlockingProvider<P> {
+    pub fn new(provider: P) -> BlockingProvider<P> {
+        BlockingProvider {
+            inner: Arc::new(provider),
+        }
+    }
+}
+
+impl<P> Clone for BlockingProvider<P> {
+    fn clone(&self) -> Self {
+        BlockingProvider {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+// Run `f` on Tokio's blocking thread pool. A panic in `f` is resumed in
+// the calling task, and a task cancelled before it ran, as when the runtime
+// shuts down, fails with `SienaError::Io`.
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, SienaError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => Ok(value),
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(SienaError::Io(format!("Blocking task failed: {}", e))),
+    }
+}
+
+impl<P: StoreProvider + 'static> AsyncStoreProvider for BlockingProvider<P> {
+    fn retrieve<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Vec<Record>> {
+        let inner = self.inner.clone();
+        let name = name.to_string();
+
+        Box::pin(async move {
+            run_blocking(move || inner.retrieve(&name))
+                .await
+                .unwrap_or_default()
+        })
+    }
+
+    fn query<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Option<Vec<Record>>> {
+        let inner = self.inner.clone();
+        let query = query.clone();
+
+        Box::pin(async move {
+            run_blocking(move || inner.query(&query))
+                .await
+                .ok()
+                .flatten()
+        })
+    }
+
+    fn set<'a>(
+        &'a self,
+        records: Vec<Record>,
+        data: Vec<(&'a str, &'a RecordData)>,
+    ) -> BoxFuture<'a, Result<Vec<Record>, SienaError>> {
+        let inner = self.inner.clone();
+        let data: Vec<(String, RecordData)> = data
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+
+        Box::pin(async move {
+            run_blocking(move || {
+                let data = data
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value))
+                    .collect();
+
+                inner.set(records, data)
+            })
+            .await?
+        })
+    }
+
+    fn set_new<'a>(
+        &'a self,
+        records: Vec<Record>,
+        data: Vec<(&'a str, &'a RecordData)>,
+    ) -> BoxFuture<'a, Result<Vec<Record>, SienaError>> {
+        let inner = self.inner.clone();
+        let data: Vec<(String, RecordData)> = data
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+
+        Box::pin(async move {
+            run_blocking(move || {
+                let data = data
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value))
+                    .collect();
+
+                inner.set_new(records, data)
+            })
+            .await?
+        })
+    }
+
+    fn delete(&self, records: Vec<Record>) -> BoxFuture<'_, Result<(), SienaError>> {
+        let inner = self.inner.clone();
+
+        Box::pin(async move { run_blocking(move || inner.delete(records)).await? })
+    }
+}
+
+pub type AsyncSiena = Siena<dyn AsyncStoreProvider>;
+
+impl Siena<dyn AsyncStoreProvider> {
+    // Run the query, see `Siena::fetch`.
+    async fn fetch(&self, fields: Option<Vec<String>>) -> Result<Vec<Record>, SienaError> {
+        let steps = self.bounded_steps()?;
+        let (query, steps) = self.plan(&steps, fields.clone());
+        let records = match query {
+            Some(query) => match self.provider.query(&query).await {
+                Some(records) => records,
+                None => query.apply(self.provider.retrieve(&query.collection).await),
+            },
+            None => Vec::new(),
+        };
+
+        Ok(self.finish(records, steps, fields))
+    }
+
+    // Get all records.
+    pub async fn get_all(self) -> Vec<Record> {
+        self.fetch(self.fields.clone()).await.unwrap_or_default()
+    }
+
+    // Get first record.
+    pub async fn get_first(self) -> Option<Record> {
+        self.limit(1).get_all().await.into_iter().next()
+    }
+
+    // Get last record.
+    pub async fn get_last(self) -> Option<Record> {
+        self.get_all().await.pop()
+    }
+
+    // Fail with `SienaError::Exists` if a record added by `create` already
+    // exists, see `Siena::ensure_new`.
+    async fn ensure_new(&self) -> Result<(), SienaError> {
+        for (record, query) in self.created() {
+            let existing = match self.provider.query(&query).await {
+                Some(records) => records,
+                None => query.apply(self.provider.retrieve(&query.collection).await),
+            };
+
+            if existing.iter().any(|r| r.id == record.id) {
+                return Err(SienaError::Exists(record.id.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Set a Vector of tuples (key, value) in all records queried,
+    // and persist them.
+    pub async fn set(self, data: Vec<(&str, &RecordData)>) -> Result<(), SienaError> {
+        self.ensure_new().await?;
+
+        let (created, records) = self.split_created(self.fetch(None).await?);
+
+        self.provider.set_new(created, data.clone()).await?;
+        self.provider.set(records, data).await?;
+
+        Ok(())
+    }
+
+    // Create a new record holding `data` with a generated id, and persist
+    // it, see `Siena::insert`.
+    pub async fn insert(
+        self,
+        collection: &str,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Record, SienaError> {
+        let query = siena::ids_query(collection);
+        let mut existing: Vec<String> = match self.provider.query(&query).await {
+            Some(records) => records,
+            None => query.apply(self.provider.retrieve(collection).await),
+        }
+        .into_iter()
+        .map(|record| record.id)
+        .collect();
+
+        loop {
+            let record = self.generated_record(collection, &data, existing.clone())?;
+
+            match self
+                .provider
+                .set_new(Vec::from([record.clone()]), data.clone())
+                .await
+            {
+                Err(SienaError::Exists(_)) => existing.push(record.id),
+                result => return Ok(result?.pop().unwrap_or(record)),
+            }
+        }
+    }
+
+    // Delete all records queried.
+    pub async fn delete(self) -> Result<(), SienaError> {
+        let records = self.fetch(None).await?;
+
+        self.provider.delete(records).await
+    }
+}
+
+pub fn async_siena(provider: impl AsyncStoreProvider + 'static) -> AsyncSiena {
+    Siena::new(Arc::new(provider))
+}
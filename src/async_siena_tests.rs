@@ -0,0 +1,109 @@
+use crate::async_siena::{async_siena, BlockingProvider};
+use crate::providers::local::LocalProvider;
+use crate::siena::{Record, RecordData, RecordSortOrder, SienaError, StoreProvider};
+use crate::siena_tests::temp_store;
+use std::env;
+
+#[tokio::test]
+async fn async_get_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let provider = BlockingProvider::new(LocalProvider::new(&local_dir));
+
+    let first = async_siena(provider.clone())
+        .collection("demo")
+        .sort("date", RecordSortOrder::Asc)
+        .get_first()
+        .await
+        .unwrap();
+
+    let all = tokio::spawn(
+        async_siena(provider.clone())
+            .collection("demo")
+            .when_has("title")
+            .get_all(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(first.id, "3");
+    assert_eq!(all.len(), 5);
+}
+
+#[tokio::test]
+async fn async_set_delete_test() {
+    let provider = BlockingProvider::new(LocalProvider::new(&temp_store("async-set-delete", &[])));
+
+    async_siena(provider.clone())
+        .create("posts", "hello")
+        .set(Vec::from([(
+            "title",
+            &RecordData::Str(String::from("Hello")),
+        )]))
+        .await
+        .unwrap();
+
+    let created = async_siena(provider.clone())
+        .collection("posts")
+        .get_last()
+        .await
+        .unwrap();
+
+    async_siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .delete()
+        .await
+        .unwrap();
+
+    let remaining = async_siena(provider.clone())
+        .collection("posts")
+        .get_all()
+        .await;
+
+    assert_eq!(
+        created.data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+    assert!(remaining.is_empty());
+}
+
+// A provider failing in the worst way on every write.
+struct PanickingProvider;
+
+impl StoreProvider for PanickingProvider {
+    fn retrieve(&self, _name: &str) -> Vec<Record> {
+        Vec::new()
+    }
+
+    fn set(
+        &self,
+        _records: Vec<Record>,
+        _data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        panic!("disk on fire");
+    }
+
+    fn delete(&self, _records: Vec<Record>) -> Result<(), SienaError> {
+        panic!("disk on fire");
+    }
+}
+
+#[tokio::test]
+async fn async_panic_test() {
+    let result = tokio::spawn(
+        async_siena(BlockingProvider::new(PanickingProvider))
+            .upsert("posts", "hello")
+            .set(Vec::new()),
+    )
+    .await;
+
+    // The panic comes through as it was, not wrapped in another one
+    let payload = result.unwrap_err().into_panic();
+
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"disk on fire"));
+}
@@ -1,7 +1,8 @@
-use crate::siena::RecordData;
+use crate::siena::{RecordData, RecordMap};
+use crate::utils::{from_toml, to_toml};
+use crate::yaml;
 use comrak::ComrakOptions;
 use regex::Regex;
-use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,42 +11,197 @@ pub enum FrontMatterError {
     RegexError(#[from] regex::Error),
     #[error("YAML error: {0}")]
     YamlError(#[from] serde_yaml::Error),
+    #[error("TOML error: {0}")]
+    TomlError(#[from] toml::de::Error),
+    #[error("TOML error: {0}")]
+    TomlSerializeError(#[from] toml::ser::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Front matter is not closed")]
+    Unclosed,
 }
 
-pub fn parse(contents: &str) -> Result<HashMap<String, RecordData>, FrontMatterError> {
-    let re = Regex::new(r"(?is)---\n(.*?)\n---")?;
-    let yaml_captures = re.captures(contents);
+// The delimiters a Markdown file's front matter is written with: `---` for
+// YAML, `+++` for TOML, or a JSON object with its own braces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrontMatterStyle {
+    #[default]
+    Yaml,
+    Toml,
+    Json,
+}
+
+// The keys Markdown records hold their body in: rendered as HTML, and as
+// the raw Markdown. They're derived from the body, so they never go in the
+// front matter.
+pub const CONTENT: &str = "content";
+pub const CONTENT_RAW: &str = "content_raw";
+
+struct FrontMatter {
+    style: FrontMatterStyle,
+    // The front matter as written, without its delimiters.
+    raw: String,
+    meta: RecordMap,
+    body: String,
+}
+
+// Split `contents` into its front matter and the Markdown after it, or
+// `None` if it has no front matter. Front matter that's opened but not
+// closed is an error, rather than part of the Markdown.
+fn split(contents: &str) -> Result<Option<FrontMatter>, FrontMatterError> {
+    let trimmed = contents.trim_start();
 
-    // Captures not found, return empty HashMap
-    if yaml_captures.is_none() {
-        return Ok(HashMap::new());
+    if trimmed.starts_with("+++") {
+        let re = Regex::new(r"(?s)\A\+\+\+\r?\n(.*?)\r?\n\+\+\+")?;
+
+        return Ok(match re.captures(trimmed) {
+            Some(captures) => Some(FrontMatter {
+                style: FrontMatterStyle::Toml,
+                raw: captures[1].to_owned(),
+                meta: from_toml(&captures[1])?,
+                body: trimmed[captures[0].len()..].trim().to_owned(),
+            }),
+            None => return Err(FrontMatterError::Unclosed),
+        });
     }
 
-    let yaml_matches = yaml_captures.unwrap();
+    if trimmed.starts_with('{') {
+        let mut stream = serde_json::Deserializer::from_str(trimmed).into_iter::<RecordMap>();
+
+        // A Markdown file may well start with a brace without it being
+        // front matter.
+        return Ok(match stream.next() {
+            Some(Ok(meta)) => Some(FrontMatter {
+                style: FrontMatterStyle::Json,
+                raw: trimmed[..stream.byte_offset()].to_owned(),
+                meta,
+                body: trimmed[stream.byte_offset()..].trim().to_owned(),
+            }),
+            _ => None,
+        });
+    }
 
-    // Captures found, but no YAML, return empty HashMap
-    if yaml_matches.len() < 2 {
-        return Ok(HashMap::new());
+    if !trimmed.starts_with("---") {
+        return Ok(None);
     }
 
-    let yaml_match = yaml_matches.get(1).unwrap();
-    let yaml_bit = &contents[yaml_match.start()..yaml_match.end()];
-    let mut data: HashMap<String, RecordData> = serde_yaml::from_str(yaml_bit)?;
+    let re = Regex::new(r"(?s)\A---\r?\n(?:(.*?)\r?\n)?---")?;
+    let captures = re.captures(trimmed).ok_or(FrontMatterError::Unclosed)?;
+    let raw = captures.get(1).map_or("", |raw| raw.as_str());
+
+    Ok(Some(FrontMatter {
+        style: FrontMatterStyle::Yaml,
+        raw: raw.to_owned(),
+        meta: match raw.trim().is_empty() {
+            true => RecordMap::new(),
+            false => serde_yaml::from_str(raw)?,
+        },
+        body: trimmed[captures[0].len()..].trim().to_owned(),
+    }))
+}
+
+// The style of the front matter of `contents`, if it has any.
+pub fn style(contents: &str) -> Option<FrontMatterStyle> {
+    split(contents).ok().flatten().map(|fm| fm.style)
+}
+
+// The Markdown after the front matter of `contents`, or all of it when it
+// has no front matter.
+pub fn body(contents: &str) -> Result<String, FrontMatterError> {
+    Ok(match split(contents)? {
+        Some(fm) => fm.body,
+        None => contents.trim().to_owned(),
+    })
+}
+
+// Parse only the front matter of `contents`, leaving out the Markdown.
+pub fn parse_meta(contents: &str) -> Result<RecordMap, FrontMatterError> {
+    Ok(split(contents)?.map(|fm| fm.meta).unwrap_or_default())
+}
+
+pub fn parse(contents: &str) -> Result<RecordMap, FrontMatterError> {
+    let FrontMatter {
+        meta: mut data,
+        body: doc,
+        ..
+    } = match split(contents)? {
+        Some(fm) => fm,
+        None => return Ok(RecordMap::new()),
+    };
 
     // Insert Markdown
-    let doc = re.replace(contents, "").into_owned().trim().to_owned();
     let md = comrak::markdown_to_html(&doc, &ComrakOptions::default());
 
-    data.insert("content".to_string(), RecordData::Str(md));
-    data.insert("content_raw".to_string(), RecordData::Str(doc.to_string()));
+    data.insert(CONTENT.to_string(), RecordData::Str(md));
+    data.insert(CONTENT_RAW.to_string(), RecordData::Str(doc));
 
     Ok(data)
 }
 
+// `meta` without the keys derived from the body.
+fn without_body(meta: &RecordMap) -> RecordMap {
+    meta.iter()
+        .filter(|(key, _)| key.as_str() != CONTENT && key.as_str() != CONTENT_RAW)
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+// Write `meta` as front matter in the given `style`, followed by the
+// Markdown `body`.
+pub fn serialize(
+    meta: &RecordMap,
+    body: &str,
+    style: FrontMatterStyle,
+) -> Result<String, FrontMatterError> {
+    let meta = &without_body(meta);
+
+    Ok(match style {
+        FrontMatterStyle::Yaml => format!("---\n{}---\n\n{}", serde_yaml::to_string(meta)?, body),
+        FrontMatterStyle::Toml => {
+            format!("+++\n{}+++\n\n{}", to_toml(meta)?, body)
+        }
+        FrontMatterStyle::Json => format!("{}\n\n{}", serde_json::to_string_pretty(meta)?, body),
+    })
+}
+
+// Write `meta` as front matter, followed by the Markdown `body`, over the
+// current `contents` of a file. YAML front matter is edited in place,
+// keeping its comments, key order and formatting, and other front matter
+// is written anew in its style. Front matter that can't be read is an
+// error, as writing over it would lose it. Files with CRLF line endings
+// keep them.
+pub fn update(contents: &str, meta: &RecordMap, body: &str) -> Result<String, FrontMatterError> {
+    if contents.contains("\r\n") {
+        let updated = update(
+            &contents.replace("\r\n", "\n"),
+            meta,
+            &body.replace("\r\n", "\n"),
+        )?;
+
+        return Ok(updated.replace('\n', "\r\n"));
+    }
+
+    let fm = split(contents)?;
+    let meta = &without_body(meta);
+
+    if let Some(FrontMatter {
+        style: FrontMatterStyle::Yaml,
+        raw,
+        ..
+    }) = &fm
+    {
+        if let Some(yaml) = yaml::update(&format!("{}\n", raw), meta) {
+            return Ok(format!("---\n{}---\n\n{}", yaml, body));
+        }
+    }
+
+    serialize(meta, body, fm.map(|fm| fm.style).unwrap_or_default())
+}
+
 #[test]
 fn parse_test() -> Result<(), FrontMatterError> {
     let seed = "---\ntitle: Hello, World\n---\n\nHi there.";
-    let expected: HashMap<String, RecordData> = HashMap::from([
+    let expected: RecordMap = RecordMap::from([
         (
             String::from("title"),
             RecordData::Str(String::from("Hello, World")),
@@ -64,3 +220,112 @@ fn parse_test() -> Result<(), FrontMatterError> {
 
     Ok(())
 }
+
+#[test]
+fn parse_toml_json_test() -> Result<(), FrontMatterError> {
+    let toml = "+++\ntitle = \"Hello, World\"\n+++\n\nHi there.";
+    let json = "{\n  \"title\": \"Hello, World\"\n}\n\nHi there.";
+
+    for seed in [toml, json] {
+        let data = parse(seed)?;
+
+        assert_eq!(
+            data.get("title"),
+            Some(&RecordData::Str(String::from("Hello, World")))
+        );
+        assert_eq!(
+            data.get("content_raw"),
+            Some(&RecordData::Str(String::from("Hi there.")))
+        );
+    }
+
+    assert_eq!(style(toml), Some(FrontMatterStyle::Toml));
+    assert_eq!(style(json), Some(FrontMatterStyle::Json));
+    assert_eq!(style("{ not front matter"), None);
+
+    Ok(())
+}
+
+#[test]
+fn serialize_test() -> Result<(), FrontMatterError> {
+    let meta = RecordMap::from([(
+        String::from("title"),
+        RecordData::Str(String::from("Hello, World")),
+    )]);
+
+    for style in [
+        FrontMatterStyle::Yaml,
+        FrontMatterStyle::Toml,
+        FrontMatterStyle::Json,
+    ] {
+        let contents = serialize(&meta, "Hi there.", style)?;
+
+        assert_eq!(self::style(&contents), Some(style));
+        assert_eq!(parse_meta(&contents)?, meta);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn update_test() -> Result<(), FrontMatterError> {
+    let contents = "---\n# Metadata\ntitle: Hello # the title\ndate: 2023-10-20\n---\n\nHi there.";
+    let mut meta = parse_meta(contents)?;
+
+    meta.insert(String::from("title"), RecordData::Str(String::from("Bye")));
+
+    assert_eq!(
+        update(contents, &meta, "Bye there.")?,
+        "---\n# Metadata\ntitle: Bye # the title\ndate: 2023-10-20\n---\n\nBye there."
+    );
+
+    let toml = "+++\ntitle = \"Hello\"\n+++\n\nHi there.";
+
+    assert_eq!(
+        style(&update(toml, &meta, "Hi there.")?),
+        Some(FrontMatterStyle::Toml)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn update_unreadable_test() {
+    let meta = RecordMap::from([(
+        String::from("status"),
+        RecordData::Str(String::from("draft")),
+    )]);
+
+    for contents in [
+        "---\ntitle: Old\nrating: 4.5\n---\n\nBody",
+        "---\ntitle: Old\n\nBody",
+        "+++\ntitle = \"Old\"\n\nBody",
+    ] {
+        assert!(update(contents, &meta, "Body").is_err(), "{}", contents);
+        assert!(body(contents).is_err(), "{}", contents);
+    }
+}
+
+#[test]
+fn parse_crlf_test() -> Result<(), FrontMatterError> {
+    let contents = "---\r\ntitle: Hello\r\n---\r\n\r\nHi there.\r\n\r\n---\r\n\r\nBye.";
+    let data = parse(contents)?;
+
+    assert_eq!(
+        data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+    assert_eq!(
+        data.get("content_raw"),
+        Some(&RecordData::Str(String::from(
+            "Hi there.\r\n\r\n---\r\n\r\nBye."
+        )))
+    );
+    assert_eq!(
+        update(contents, &data, "Hi there.")?,
+        "---\r\ntitle: Hello\r\n---\r\n\r\nHi there."
+    );
+    assert!(parse_meta("Hi there.\n\n---\ntitle: Hello\n---\n")?.is_empty());
+
+    Ok(())
+}
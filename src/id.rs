@@ -0,0 +1,145 @@
+use crate::siena::{RecordData, RecordMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// How `insert` generates the ids of new records.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub enum IdStrategy {
+    // A slug of the value of a key, like `title`, e.g. `hello-world`.
+    Slug(String),
+    // A slug of the value of a key, prefixed with the current date (UTC),
+    // e.g. `2024-01-05-hello-world`.
+    DatedSlug(String),
+    // A random UUID (v4).
+    Uuid,
+    // A ULID, which sorts by the time it was generated in.
+    #[default]
+    Ulid,
+    // The highest numeric id of the collection plus one, starting from 1.
+    Increment,
+}
+
+impl IdStrategy {
+    // Generate an id for a record holding `data`, that isn't one of the
+    // `existing` ids, by adding `-2`, `-3` and so on to it when it is.
+    // Returns `None` when the strategy has nothing to generate it from.
+    pub fn generate(&self, data: &RecordMap, existing: &[String]) -> Option<String> {
+        let slug = |key: &str| match data.get(key) {
+            Some(RecordData::Str(value)) => {
+                Some(slug::slugify(value)).filter(|slug| !slug.is_empty())
+            }
+            _ => None,
+        };
+
+        let id = match self {
+            IdStrategy::Slug(key) => slug(key)?,
+            IdStrategy::DatedSlug(key) => format!("{}-{}", today(), slug(key)?),
+            IdStrategy::Uuid => uuid::Uuid::new_v4().to_string(),
+            IdStrategy::Ulid => ulid::Ulid::new().to_string().to_lowercase(),
+            IdStrategy::Increment => {
+                let last = existing
+                    .iter()
+                    .filter_map(|id| id.parse::<usize>().ok())
+                    .max()
+                    .unwrap_or(0);
+
+                (last + 1).to_string()
+            }
+        };
+
+        Some(unique(id, existing))
+    }
+}
+
+// `id`, or the first of `id-2`, `id-3` and so on that isn't taken.
+fn unique(id: String, existing: &[String]) -> String {
+    if !existing.contains(&id) {
+        return id;
+    }
+
+    (2..)
+        .map(|n| format!("{}-{}", id, n))
+        .find(|candidate| !existing.contains(candidate))
+        .unwrap_or(id)
+}
+
+// The current date in UTC, as `YYYY-MM-DD`.
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    date(secs / 86400)
+}
+
+// The date `days` days after 1970-01-01, as `YYYY-MM-DD`, following
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn date(days: u64) -> String {
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[test]
+fn generate_test() {
+    let data = RecordMap::from([(
+        String::from("title"),
+        RecordData::Str(String::from("Hello, World!")),
+    )]);
+    let existing = Vec::from([
+        String::from("hello-world"),
+        String::from("hello-world-2"),
+        String::from("3"),
+        String::from("about"),
+    ]);
+    let slug = IdStrategy::Slug(String::from("title"));
+
+    assert_eq!(slug.generate(&data, &[]), Some(String::from("hello-world")));
+    assert_eq!(
+        slug.generate(&data, &existing),
+        Some(String::from("hello-world-3"))
+    );
+    assert_eq!(
+        IdStrategy::Slug(String::from("name")).generate(&data, &existing),
+        None
+    );
+    assert_eq!(
+        IdStrategy::DatedSlug(String::from("title")).generate(&data, &existing),
+        Some(format!("{}-hello-world", today()))
+    );
+    assert_eq!(
+        IdStrategy::Increment.generate(&data, &existing),
+        Some(String::from("4"))
+    );
+    assert_eq!(
+        IdStrategy::Increment.generate(&data, &[]),
+        Some(String::from("1"))
+    );
+    assert_eq!(
+        IdStrategy::Uuid
+            .generate(&data, &existing)
+            .map(|id| id.len()),
+        Some(36)
+    );
+    assert_eq!(
+        IdStrategy::Ulid
+            .generate(&data, &existing)
+            .map(|id| id.len()),
+        Some(26)
+    );
+}
+
+#[test]
+fn date_test() {
+    assert_eq!(date(0), "1970-01-01");
+    assert_eq!(date(19727), "2024-01-05");
+    assert_eq!(date(19782), "2024-02-29");
+}
@@ -1,7 +1,16 @@
+#[cfg(feature = "async")]
+pub mod async_siena;
 pub mod frontmatter;
+pub mod id;
+pub mod pagination;
 pub mod providers;
+pub mod query;
 pub mod siena;
+pub mod transaction;
 pub mod utils;
+pub mod yaml;
 
+#[cfg(all(test, feature = "async"))]
+mod async_siena_tests;
 #[cfg(test)]
 mod siena_tests;
@@ -0,0 +1,192 @@
+use crate::query;
+use crate::siena::{Record, RecordMap, RecordSortOrder, SienaError};
+use crate::utils::{hex_decode, hex_encode};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+// A page of records fetched with `get_page`, with opaque cursors to
+// fetch the pages before and after it with `before` and `after`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorPage {
+    pub records: Vec<Record>,
+    pub total: usize,
+    pub has_next: bool,
+    pub has_prev: bool,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+// A page of records fetched with `paginate_with_meta`, along with what's
+// needed to render pagination links. Pages start from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub records: Vec<Record>,
+    pub total: usize,
+    pub total_pages: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub prev_page: Option<usize>,
+    pub next_page: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum CursorBound {
+    After(String),
+    Before(String),
+}
+
+// The position of a record in a sorted collection: its id and the values
+// of the sort keys.
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    id: String,
+    data: RecordMap,
+}
+
+fn encode(record: &Record, sort: &[(String, RecordSortOrder)]) -> String {
+    let data = sort
+        .iter()
+        .filter_map(|(key, _)| Some((key.clone(), record.data.get(key)?.clone())))
+        .collect();
+
+    let cursor = Cursor {
+        id: record.id.clone(),
+        data,
+    };
+
+    hex_encode(serde_json::to_string(&cursor).unwrap().as_bytes())
+}
+
+fn decode(cursor: &str) -> Result<Record, SienaError> {
+    let invalid = || SienaError::InvalidCursor(cursor.to_string());
+    let json = hex_decode(cursor).ok_or_else(invalid)?;
+    let cursor: Cursor = serde_json::from_slice(&json).map_err(|_| invalid())?;
+
+    Ok(Record {
+        id: cursor.id,
+        data: cursor.data,
+        ..Default::default()
+    })
+}
+
+// A cursor given to `after` or `before`, decoded for keeping the records
+// on its side of it in the order of the `sort` keys.
+#[derive(Debug, Clone)]
+pub(crate) struct Bound {
+    after: bool,
+    cursor: Record,
+    sort: Vec<(String, RecordSortOrder)>,
+}
+
+impl Bound {
+    pub(crate) fn new(
+        bound: &CursorBound,
+        sort: &[(String, RecordSortOrder)],
+    ) -> Result<Bound, SienaError> {
+        let (after, cursor) = match bound {
+            CursorBound::After(cursor) => (true, cursor),
+            CursorBound::Before(cursor) => (false, cursor),
+        };
+
+        Ok(Bound {
+            after,
+            cursor: decode(cursor)?,
+            sort: sort.to_vec(),
+        })
+    }
+
+    pub(crate) fn matches(&self, record: &Record) -> bool {
+        let ordering = compare(record, &self.cursor, &self.sort);
+
+        match self.after {
+            true => ordering.is_gt(),
+            false => ordering.is_lt(),
+        }
+    }
+}
+
+// Compare two records by all `sort` keys, the last one first as with
+// chained `sort` calls, and finally by id, so that no two records are equal.
+fn compare(a: &Record, b: &Record, sort: &[(String, RecordSortOrder)]) -> Ordering {
+    sort.iter()
+        .rev()
+        .map(|(key, order)| query::compare(a, b, key, order))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.id.cmp(&b.id))
+}
+
+// Cut the page of at most `limit` records out of all the matching
+// `records`, right after or before the record the `bound` cursor points at.
+pub(crate) fn cursor_page(
+    mut records: Vec<Record>,
+    sort: &[(String, RecordSortOrder)],
+    bound: Option<&CursorBound>,
+    limit: usize,
+) -> Result<CursorPage, SienaError> {
+    records.sort_by(|a, b| compare(a, b, sort));
+
+    let total = records.len();
+    let (start, end) = match bound {
+        Some(CursorBound::After(cursor)) => {
+            let cursor = decode(cursor)?;
+            let start = records.partition_point(|r| compare(r, &cursor, sort).is_le());
+
+            (start, start.saturating_add(limit).min(total))
+        }
+        Some(CursorBound::Before(cursor)) => {
+            let cursor = decode(cursor)?;
+            let end = records.partition_point(|r| compare(r, &cursor, sort).is_lt());
+
+            (end.saturating_sub(limit), end)
+        }
+        None => (0, limit.min(total)),
+    };
+
+    let records: Vec<Record> = records.drain(start..end).collect();
+    let has_prev = start > 0;
+    let has_next = end < total;
+
+    Ok(CursorPage {
+        next_cursor: match has_next {
+            true => records.last().map(|r| encode(r, sort)),
+            false => None,
+        },
+        prev_cursor: match has_prev {
+            true => records.first().map(|r| encode(r, sort)),
+            false => None,
+        },
+        records,
+        total,
+        has_next,
+        has_prev,
+    })
+}
+
+// Cut the given `page` of `per_page` records out of all the matching
+// `records`. The page 0 is treated as the first page.
+pub(crate) fn page(mut records: Vec<Record>, page: usize, per_page: usize) -> Page {
+    let page = page.max(1);
+    let total = records.len();
+    let total_pages = match per_page {
+        0 => 0,
+        _ => total.div_ceil(per_page),
+    };
+    let start = (page - 1).saturating_mul(per_page).min(total);
+    let end = start.saturating_add(per_page).min(total);
+
+    Page {
+        records: records.drain(start..end).collect(),
+        total,
+        total_pages,
+        page,
+        per_page,
+        prev_page: match page {
+            1 => None,
+            _ => Some(page - 1),
+        },
+        next_page: match page < total_pages {
+            true => Some(page + 1),
+            false => None,
+        },
+    }
+}
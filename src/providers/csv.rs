@@ -0,0 +1,314 @@
+use crate::siena::{Record, RecordData, SienaError, StoreProvider};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+// A provider treating each `{collection}.csv` file in `directory` as a
+// collection, with one record per row. The `id_column` holds the id of
+// each record, and the other columns make up its data.
+#[derive(Clone, Debug)]
+pub struct CsvProvider {
+    pub directory: String,
+    pub id_column: String,
+    lock: Arc<Mutex<()>>,
+}
+
+// The header and rows of a CSV file.
+#[derive(Default)]
+struct Table {
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl CsvProvider {
+    pub fn new(directory: &str) -> CsvProvider {
+        CsvProvider {
+            directory: directory.to_string(),
+            id_column: String::from("id"),
+            lock: Arc::default(),
+        }
+    }
+
+    // Use the column with the given `name` as the id of records.
+    pub fn with_id_column(self, name: &str) -> CsvProvider {
+        CsvProvider {
+            id_column: name.to_string(),
+            ..self
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        PathBuf::from(&self.directory).join(format!("{}.csv", name))
+    }
+
+    // Read the table of the collection with the given `name`, which is
+    // empty when there's no file for it yet. With `strict` set, as when the
+    // table is about to be written back, fails with `SienaError::Io` when
+    // the file or any of its rows can't be read, instead of skipping them.
+    fn read(&self, name: &str, strict: bool) -> Result<Table, SienaError> {
+        let path = self.path(name);
+        let io_error = |e: ::csv::Error| SienaError::Io(format!("{}: {}", path.display(), e));
+        let mut reader = match ::csv::ReaderBuilder::new().flexible(true).from_path(&path) {
+            Ok(reader) => reader,
+            Err(e) if strict && path.exists() => return Err(io_error(e)),
+            Err(_) => return Ok(Table::default()),
+        };
+        let header = match reader.headers() {
+            Ok(header) => header.iter().map(String::from).collect(),
+            Err(e) if strict => return Err(io_error(e)),
+            Err(_) => Vec::new(),
+        };
+        let mut rows = Vec::new();
+
+        for row in reader.records() {
+            match row {
+                Ok(row) => rows.push(row.iter().map(String::from).collect()),
+                Err(e) if strict => return Err(io_error(e)),
+                Err(_) => continue,
+            }
+        }
+
+        Ok(Table { header, rows })
+    }
+
+    fn write(&self, name: &str, table: &Table) -> Result<(), SienaError> {
+        let path = self.path(name);
+        let io_error =
+            |e: &dyn std::fmt::Display| SienaError::Io(format!("{}: {}", path.display(), e));
+
+        fs::create_dir_all(&self.directory).map_err(|e| io_error(&e))?;
+
+        let mut writer = ::csv::Writer::from_path(&path).map_err(|e| io_error(&e))?;
+
+        for row in std::iter::once(&table.header).chain(&table.rows) {
+            writer.write_record(row).map_err(|e| io_error(&e))?;
+        }
+
+        writer.flush().map_err(|e| io_error(&e))
+    }
+
+    // Set `data` in the `records` and write them, with the lock held.
+    fn write_records(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let mut updated_records: Vec<Record> = Vec::new();
+        let mut collections: HashMap<String, Vec<Record>> = HashMap::new();
+
+        for mut record in records {
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            record.file_name = format!("{}.csv", record.collection);
+            updated_records.push(record.clone());
+            collections
+                .entry(record.collection.clone())
+                .or_default()
+                .push(record);
+        }
+
+        for (name, records) in collections {
+            let mut table = self.read(&name, true)?;
+
+            if self.id_index(&table).is_none() {
+                table.header.insert(0, self.id_column.clone());
+
+                for row in table.rows.iter_mut() {
+                    row.insert(0, String::new());
+                }
+            }
+
+            // New keys become new columns, at the end.
+            let mut new_columns: Vec<String> = records
+                .iter()
+                .flat_map(|record| record.data.keys())
+                .filter(|key| !table.header.contains(key))
+                .cloned()
+                .collect();
+
+            new_columns.sort();
+            new_columns.dedup();
+
+            for row in table.rows.iter_mut() {
+                row.resize(table.header.len() + new_columns.len(), String::new());
+            }
+
+            table.header.extend(new_columns);
+
+            let id_index = self.id_index(&table).unwrap();
+
+            for record in records {
+                let row = self.to_row(&table, &record);
+
+                match table.rows.iter_mut().find(|r| r[id_index] == record.id) {
+                    Some(existing) => *existing = row,
+                    None => table.rows.push(row),
+                }
+            }
+
+            self.write(&name, &table)?;
+        }
+
+        Ok(updated_records)
+    }
+
+    fn id_index(&self, table: &Table) -> Option<usize> {
+        table
+            .header
+            .iter()
+            .position(|column| column == &self.id_column)
+    }
+
+    fn to_record(&self, name: &str, table: &Table, row: &[String]) -> Option<Record> {
+        let id = row.get(self.id_index(table)?)?;
+
+        if id.is_empty() {
+            return None;
+        }
+
+        let data = table
+            .header
+            .iter()
+            .zip(row)
+            .filter(|(column, _)| *column != &self.id_column)
+            .filter_map(|(column, cell)| Some((column.clone(), parse_cell(cell)?)))
+            .collect();
+
+        Some(Record {
+            id: id.clone(),
+            collection: name.to_string(),
+            file_name: format!("{}.csv", name),
+            data,
+            ..Default::default()
+        })
+    }
+
+    fn to_row(&self, table: &Table, record: &Record) -> Vec<String> {
+        table
+            .header
+            .iter()
+            .map(|column| match column == &self.id_column {
+                true => record.id.clone(),
+                false => record.data.get(column).map(format_cell).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+// Infer the `RecordData` of a cell. Empty cells are left out of the record.
+// Cells are only read as numbers when they'd be written back the same, so
+// that values like `00123` stay text.
+fn parse_cell(cell: &str) -> Option<RecordData> {
+    if cell.is_empty() {
+        return None;
+    }
+
+    if let Ok(bool) = cell.parse::<bool>() {
+        return Some(RecordData::Bool(bool));
+    }
+
+    if let Some(num) = cell
+        .parse::<usize>()
+        .ok()
+        .filter(|num| num.to_string() == cell)
+    {
+        return Some(RecordData::Num(num));
+    }
+
+    // Maps and lists are stored as JSON.
+    if cell.starts_with('[') || cell.starts_with('{') {
+        if let Ok(data) = serde_json::from_str(cell) {
+            return Some(data);
+        }
+    }
+
+    Some(RecordData::Str(cell.to_string()))
+}
+
+fn format_cell(data: &RecordData) -> String {
+    match data {
+        RecordData::Str(str) => str.clone(),
+        RecordData::Num(num) => num.to_string(),
+        RecordData::Bool(bool) => bool.to_string(),
+        RecordData::Null => String::new(),
+        data => serde_json::to_string(data).unwrap_or_default(),
+    }
+}
+
+impl StoreProvider for CsvProvider {
+    // Records are returned in the order of their rows.
+    fn retrieve(&self, name: &str) -> Vec<Record> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let table = self.read(name, false).unwrap_or_default();
+
+        table
+            .rows
+            .iter()
+            .filter_map(|row| self.to_record(name, &table, row))
+            .collect()
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        self.write_records(records, data)
+    }
+
+    // Records are checked and written while holding the lock.
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        for record in &records {
+            let table = self.read(&record.collection, true)?;
+
+            if table
+                .rows
+                .iter()
+                .filter_map(|row| self.to_record(&record.collection, &table, row))
+                .any(|r| r.id == record.id)
+            {
+                return Err(SienaError::Exists(record.id.clone()));
+            }
+        }
+
+        self.write_records(records, data)
+    }
+
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut collections: HashMap<String, Vec<String>> = HashMap::new();
+
+        for record in records {
+            collections
+                .entry(record.collection)
+                .or_default()
+                .push(record.id);
+        }
+
+        for (name, ids) in collections {
+            let mut table = self.read(&name, true)?;
+
+            if let Some(id_index) = self.id_index(&table) {
+                table
+                    .rows
+                    .retain(|row| !row.get(id_index).is_some_and(|id| ids.contains(id)));
+                self.write(&name, &table)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use crate::providers::csv::CsvProvider;
+use crate::siena::{siena, Record, RecordData, RecordMap, SienaError, StoreProvider};
+use crate::siena_tests::temp_store;
+use std::fs;
+
+fn provider(name: &str) -> CsvProvider {
+    let directory = temp_store(
+        &format!("csv-{}", name),
+        &[(
+            "products.csv",
+            "sku,name,price,in_stock,tags\n\
+             a1,Chair,40,true,\"[\"\"wood\"\"]\"\n\
+             b2,Table,,false,\n",
+        )],
+    );
+
+    CsvProvider::new(&directory).with_id_column("sku")
+}
+
+#[test]
+fn retrieve_test() {
+    let records = provider("retrieve").retrieve("products");
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].id, "a1");
+    assert_eq!(
+        records[0].data,
+        RecordMap::from([
+            (String::from("name"), RecordData::Str(String::from("Chair"))),
+            (String::from("price"), RecordData::Num(40)),
+            (String::from("in_stock"), RecordData::Bool(true)),
+            (
+                String::from("tags"),
+                RecordData::Vec(Vec::from([RecordData::Str(String::from("wood"))]))
+            ),
+        ])
+    );
+    assert!(!records[1].data.contains_key("price"));
+    assert!(provider("retrieve-missing").retrieve("orders").is_empty());
+}
+
+#[test]
+fn set_test() {
+    let provider = provider("set");
+
+    siena(provider.clone())
+        .collection("products")
+        .when_is("name", "Table")
+        .set(Vec::from([("price", &RecordData::Num(90))]))
+        .unwrap();
+
+    siena(provider.clone())
+        .create("products", "c3")
+        .set(Vec::from([(
+            "color",
+            &RecordData::Str(String::from("red")),
+        )]))
+        .unwrap();
+
+    let contents = fs::read_to_string(format!("{}/products.csv", provider.directory)).unwrap();
+
+    assert_eq!(
+        contents,
+        "sku,name,price,in_stock,tags,color\n\
+         a1,Chair,40,true,\"[\"\"wood\"\"]\",\n\
+         b2,Table,90,false,,\n\
+         c3,,,,,red\n"
+    );
+}
+
+#[test]
+fn set_new_test() {
+    let provider = provider("set-new");
+    let before = fs::read_to_string(format!("{}/products.csv", provider.directory)).unwrap();
+    let record = |sku: &str| Record {
+        id: sku.to_string(),
+        collection: String::from("products"),
+        ..Default::default()
+    };
+    let result = provider.set_new(
+        Vec::from([record("a1")]),
+        Vec::from([("price", &RecordData::Num(1))]),
+    );
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "a1"));
+    assert_eq!(
+        fs::read_to_string(format!("{}/products.csv", provider.directory)).unwrap(),
+        before
+    );
+
+    provider
+        .set_new(
+            Vec::from([record("c3")]),
+            Vec::from([("price", &RecordData::Num(1))]),
+        )
+        .unwrap();
+
+    assert!(provider.retrieve("products").iter().any(|r| r.id == "c3"));
+}
+
+#[test]
+fn delete_test() {
+    let provider = provider("delete");
+
+    siena(provider.clone())
+        .collection("products")
+        .when_is("id", "a1")
+        .delete()
+        .unwrap();
+
+    let records = provider.retrieve("products");
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].id, "b2");
+}
+
+#[test]
+fn set_keeps_text_test() {
+    let provider = provider("set-keeps-text");
+
+    fs::write(
+        format!("{}/parts.csv", provider.directory),
+        "sku,code,count\na1,00123,7\n",
+    )
+    .unwrap();
+
+    let records = provider.retrieve("parts");
+
+    assert_eq!(
+        records[0].data.get("code"),
+        Some(&RecordData::Str(String::from("00123")))
+    );
+
+    siena(provider.clone())
+        .collection("parts")
+        .when_is("id", "a1")
+        .set(Vec::from([("count", &RecordData::Num(8))]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/parts.csv", provider.directory)).unwrap(),
+        "sku,code,count\na1,00123,8\n"
+    );
+}
+
+#[test]
+fn set_unreadable_test() {
+    let provider = provider("set-unreadable");
+    let path = format!("{}/parts.csv", provider.directory);
+    let contents = b"sku,name\na1,Bolt\nb2,\xff\xfe\n".to_vec();
+
+    fs::write(&path, &contents).unwrap();
+
+    assert_eq!(provider.retrieve("parts").len(), 1);
+
+    let result = siena(provider.clone())
+        .collection("parts")
+        .when_is("id", "a1")
+        .set(Vec::from([("name", &RecordData::Str(String::from("Nut")))]));
+
+    assert!(matches!(result, Err(SienaError::Io(_))));
+    assert_eq!(fs::read(&path).unwrap(), contents);
+}
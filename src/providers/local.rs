@@ -1,39 +1,161 @@
 use thiserror::Error;
 
 use crate::{
-    frontmatter,
-    siena::{Record, RecordData, StoreProvider},
-    utils::str_ends_with_any,
+    frontmatter::{self, FrontMatterStyle},
+    query::Query,
+    siena::{Record, RecordData, RecordMap, SienaError, StoreProvider},
+    transaction::Change,
+    utils::{from_toml, hex_encode, str_ends_with_any, to_toml},
+    yaml,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, TryLockError};
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc, Mutex, OnceLock, RwLock,
+    },
 };
-use std::fs;
-use std::{collections::HashMap, fs::DirEntry};
 
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
+    #[error("YAML error: {0}")]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+// A relative path with `/` as separator, whatever the platform.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// The version of a file with the given `contents`: a SHA-256 hash of
+// them, which changes whenever the file does, but not between releases.
+pub(crate) fn version(contents: &str) -> String {
+    hex_encode(&Sha256::digest(contents.as_bytes()))
+}
+
+const BUNDLE_INDEXES: [&str; 2] = ["index.md", "index.markdown"];
+
+const RECORD_EXTENSIONS: [&str; 6] = ["yml", "yaml", "json", "toml", "md", "markdown"];
+
+// Whether the file at `path` has the extension of a record file.
+fn is_record_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| RECORD_EXTENSIONS.contains(&ext))
+}
+
+// The index file of the page bundle in `dir`, if it is one. A directory
+// that holds other record files next to its index is a nested collection
+// instead, so that those records aren't taken for assets.
+fn bundle_index(dir: &Path) -> Option<PathBuf> {
+    let index = BUNDLE_INDEXES
+        .iter()
+        .map(|index| dir.join(index))
+        .find(|index| index.is_file())?;
+    let holds_records = walk(dir)
+        .iter()
+        .any(|file| dir.join(file) != index && is_record_file(file));
+
+    match holds_records {
+        true => None,
+        false => Some(index),
+    }
+}
+
+// Paths of all files in `dir` and its subdirectories, relative to `dir`.
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::from([dir.to_path_buf()]);
+    let mut files = Vec::new();
+
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(current).into_iter().flatten().flatten() {
+            let path = entry.path();
+
+            match path.is_dir() {
+                true => dirs.push(path),
+                false => files.extend(path.strip_prefix(dir).ok().map(Path::to_path_buf)),
+            }
+        }
+    }
+
+    files.sort();
+    files
 }
 
-fn parse_file(file: &DirEntry, collection: &str) -> Result<Record, ParseError> {
-    let contents = fs::read_to_string(file.path())?;
-    let file_name = file.file_name();
-    let mut data = HashMap::new();
-    let id = file_name
-        .to_str()
-        .unwrap()
-        .replace(".yml", "")
-        .replace(".yaml", "")
-        .replace(".md", "")
-        .replace(".markdown", "");
+// Parse the file at `path` into a record of the `collection` stored in the
+// `root` directory, with its path relative to `root`, sans extension, as id.
+// The index file of a page bundle gives a record with the bundle's path as
+// id, and the other files of the bundle as assets.
+fn parse_file(
+    path: &Path,
+    root: &Path,
+    collection: &str,
+    with_body: bool,
+) -> Result<Record, ParseError> {
+    let contents = fs::read_to_string(path)?;
+    let file_name = path.file_name().unwrap();
+    let mut data = RecordMap::new();
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let mut id = slash_path(&relative.with_extension(""));
+    let mut parent = relative
+        .parent()
+        .map(slash_path)
+        .filter(|parent| !parent.is_empty());
+    let mut assets = Vec::new();
 
-    if str_ends_with_any(file.path().to_str().unwrap(), Vec::from(["yml", "yaml"])) {
+    if let Some(bundle) = parent.clone() {
+        if bundle_index(path.parent().unwrap()).as_deref() == Some(path) {
+            assets = walk(path.parent().unwrap())
+                .iter()
+                .map(|asset| slash_path(asset))
+                .filter(|asset| asset != file_name.to_str().unwrap())
+                .collect();
+            parent = bundle
+                .rsplit_once('/')
+                .map(|(parent, _)| parent.to_string());
+            id = bundle;
+        }
+    }
+
+    if str_ends_with_any(path.to_str().unwrap(), Vec::from(["yml", "yaml"])) {
         if let Ok(yaml) = serde_yaml::from_str(&contents) {
             data = yaml;
         }
     }
 
-    if str_ends_with_any(file.path().to_str().unwrap(), Vec::from(["md", "markdown"])) {
-        if let Ok(fm) = frontmatter::parse(&contents) {
+    if str_ends_with_any(path.to_str().unwrap(), Vec::from(["json"])) {
+        if let Ok(json) = serde_json::from_str(&contents) {
+            data = json;
+        }
+    }
+
+    if str_ends_with_any(path.to_str().unwrap(), Vec::from(["toml"])) {
+        if let Ok(toml) = from_toml(&contents) {
+            data = toml;
+        }
+    }
+
+    if str_ends_with_any(path.to_str().unwrap(), Vec::from(["md", "markdown"])) {
+        let fm = match with_body {
+            true => frontmatter::parse(&contents),
+            false => frontmatter::parse_meta(&contents),
+        };
+
+        if let Ok(fm) = fm {
             data = fm;
         }
     }
@@ -42,107 +164,1144 @@ fn parse_file(file: &DirEntry, collection: &str) -> Result<Record, ParseError> {
         id,
         collection: collection.to_string(),
         file_name: file_name.to_str().unwrap().to_string(),
+        path: slash_path(relative),
+        parent,
+        assets,
+        version: Some(version(&contents)),
         data,
     })
 }
 
-#[derive(Clone)]
+// The elements of a file holding a whole collection as a YAML sequence, a
+// JSON array or JSON Lines.
+fn read_collection_file(path: &Path) -> Result<(String, Vec<RecordMap>), ParseError> {
+    let contents = fs::read_to_string(path)?;
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    let elements = match str_ends_with_any(file_name, Vec::from(["jsonl"])) {
+        true => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+        false => match str_ends_with_any(file_name, Vec::from(["json"])) {
+            true => serde_json::from_str(&contents)?,
+            false => serde_yaml::from_str(&contents)?,
+        },
+    };
+
+    Ok((contents, elements))
+}
+
+// The id of an element of a collection file, which may be a number.
+fn element_id(data: &RecordMap) -> Option<String> {
+    match data.get("id")? {
+        RecordData::Str(id) => Some(id.clone()),
+        RecordData::Num(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+// Parse a file holding a whole collection, where each element is a record
+// with an `id` field. As the file is written as a whole, its records share
+// its version.
+fn parse_collection_file(path: &Path, collection: &str) -> Result<Vec<Record>, ParseError> {
+    let (contents, elements) = read_collection_file(path)?;
+    let file_name = path.file_name().unwrap().to_str().unwrap();
+    let file_version = version(&contents);
+
+    Ok(elements
+        .into_iter()
+        .filter_map(|mut data| {
+            let id = element_id(&data)?;
+
+            data.shift_remove("id");
+
+            Some(Record {
+                id,
+                collection: collection.to_string(),
+                file_name: file_name.to_string(),
+                version: Some(file_version.clone()),
+                data,
+                ..Default::default()
+            })
+        })
+        .collect())
+}
+
+// Serialize `records` for a file holding a whole collection, in the format
+// matching its extension. Records already in the file keep their `id` as
+// it's written there, in type and position, and others get a string one.
+fn serialize_collection(path: &Path, records: &[Record]) -> Result<String, SienaError> {
+    let ids: HashMap<String, (usize, RecordData)> = read_collection_file(path)
+        .map(|(_, elements)| elements)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|data| {
+            let index = data.get_index_of("id")?;
+
+            Some((element_id(&data)?, (index, data.get("id")?.clone())))
+        })
+        .collect();
+    let elements: Vec<RecordMap> = records
+        .iter()
+        .map(|record| {
+            let mut data = record.data.clone();
+            let (index, id) = match ids.get(&record.id) {
+                Some((index, id)) => ((*index).min(data.len()), id.clone()),
+                None => (0, RecordData::Str(record.id.clone())),
+            };
+
+            data.shift_insert(index, String::from("id"), id);
+            data
+        })
+        .collect();
+
+    let file_name = path.to_str().unwrap();
+    let contents = match str_ends_with_any(file_name, Vec::from(["jsonl"])) {
+        true => elements
+            .iter()
+            .map(|data| serde_json::to_string(data).map(|line| line + "\n"))
+            .collect::<Result<String, _>>()
+            .ok(),
+        false => match str_ends_with_any(file_name, Vec::from(["json"])) {
+            true => serde_json::to_string_pretty(&elements).ok(),
+            false => serde_yaml::to_string(&elements).ok(),
+        },
+    };
+
+    contents.ok_or_else(|| SienaError::Io(format!("Could not serialize {}", file_name)))
+}
+
+// Write `records` to a file holding a whole collection, returning the
+// file's new version.
+fn write_collection_file(path: &Path, records: &[Record]) -> Result<String, SienaError> {
+    let contents = serialize_collection(path, records)?;
+
+    write_atomic(path, contents.as_bytes()).map_err(io_error(path))?;
+
+    Ok(version(&contents))
+}
+
+// Serialize the `record` in the format matching its file extension, over
+// the `existing` contents of its file, if any, so that YAML and Markdown
+// front matter keep their comments, key order and formatting.
+fn serialize_record(record: &Record, existing: Option<&str>) -> Option<String> {
+    // yaml
+    if str_ends_with_any(record.file_name.as_ref(), Vec::from(["yml", "yaml"])) {
+        if let Some(yaml) = existing.and_then(|existing| yaml::update(existing, &record.data)) {
+            return Some(yaml);
+        }
+
+        return serde_yaml::to_string(&record.data).ok();
+    }
+
+    // json
+    if str_ends_with_any(record.file_name.as_ref(), Vec::from(["json"])) {
+        return serde_json::to_string_pretty(&record.data).ok();
+    }
+
+    // toml
+    if str_ends_with_any(record.file_name.as_ref(), Vec::from(["toml"])) {
+        return to_toml(&record.data).ok();
+    }
+
+    // frontmatter, with the body the file already has unless a new one is set
+    if str_ends_with_any(record.file_name.as_ref(), Vec::from(["md", "markdown"])) {
+        let body = match record.data.get(frontmatter::CONTENT_RAW) {
+            Some(RecordData::Str(body)) => body.clone(),
+            _ => match existing {
+                Some(existing) => frontmatter::body(existing).ok()?,
+                None => String::new(),
+            },
+        };
+
+        return match existing {
+            Some(existing) => frontmatter::update(existing, &record.data, &body).ok(),
+            None => frontmatter::serialize(&record.data, &body, FrontMatterStyle::default()).ok(),
+        };
+    }
+
+    None
+}
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// A unique hidden path next to `path`, with the given `extension`, for
+// temporary files and backups.
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let directory = path.parent().unwrap_or(Path::new("."));
+
+    directory.join(format!(
+        ".{}.{}.{}.{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, AtomicOrdering::Relaxed),
+        extension
+    ))
+}
+
+// Write `contents` to a temporary file next to `path`, synced to disk,
+// returning the temporary file's path.
+fn write_temp(path: &Path, contents: &[u8]) -> std::io::Result<PathBuf> {
+    let temp_path = sibling_path(path, "tmp");
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+
+        file.write_all(contents)?;
+        file.sync_all()
+    })();
+
+    match result {
+        Ok(()) => Ok(temp_path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+// Write `contents` to `path` so that the file holds either its previous or
+// its new contents, even if the process crashes mid-write: the contents go
+// to a temporary file in the same directory first, which is synced to disk
+// and then renamed over `path`.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let temp_path = write_temp(path, contents)?;
+
+    if let Err(e) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    // Sync the directory too, so that the rename itself is durable. Not
+    // every platform can open directories, so this is best effort.
+    if let Ok(dir) = fs::File::open(directory) {
+        let _ = dir.sync_all();
+    }
+
+    Ok(())
+}
+
+// Write `contents` to a new file at `path`, failing with
+// `ErrorKind::AlreadyExists` when there's a file there already, even one
+// another process just wrote.
+fn write_new(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    let result = file.write_all(contents).and_then(|_| file.sync_all());
+
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+
+    result
+}
+
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> SienaError + '_ {
+    move |e| SienaError::Io(format!("{}: {}", path.display(), e))
+}
+
+// How long `set`, `delete` and `rename` wait for the file locks held by
+// other processes writing to the same directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockWait {
+    // Fail right away with `SienaError::LockTimeout`.
+    NoWait,
+    // Wait up to the given duration before failing.
+    Timeout(Duration),
+    // Wait for as long as it takes.
+    #[default]
+    Forever,
+}
+
+// What `write` makes sure of before writing records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteMode {
+    // Nothing, records are written over whatever is there.
+    Overwrite,
+    // That records are still at their version, failing with
+    // `SienaError::Conflict` otherwise.
+    IfUnchanged,
+    // That records don't exist yet, failing with `SienaError::Exists`
+    // otherwise.
+    New,
+}
+
+// The directory in the store holding the lock files.
+const LOCKS_DIR: &str = ".locks";
+
+// Acquire an advisory lock on the lock file at `path`, which can be held
+// by many at once when `shared`, and by one only otherwise. The lock is
+// released when the returned file is dropped.
+fn lock_file(path: &Path, shared: bool, wait: LockWait) -> Result<fs::File, SienaError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(io_error(parent))?;
+    }
+
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(io_error(path))?;
+
+    if wait == LockWait::Forever {
+        match shared {
+            true => file.lock_shared(),
+            false => file.lock(),
+        }
+        .map_err(io_error(path))?;
+
+        return Ok(file);
+    }
+
+    let deadline = match wait {
+        LockWait::Timeout(timeout) => Instant::now() + timeout,
+        _ => Instant::now(),
+    };
+
+    loop {
+        let result = match shared {
+            true => file.try_lock_shared(),
+            false => file.try_lock(),
+        };
+
+        match result {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::Error(e)) => return Err(io_error(path)(e)),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => {
+                return Err(SienaError::LockTimeout(path.display().to_string()));
+            }
+        }
+    }
+}
+
+// The directory in the store holding the journals of the transactions
+// being committed.
+const JOURNALS_DIR: &str = ".journal";
+
+// Remove the file or directory at `path`, if there's one.
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    let result = match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    };
+
+    match result {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// The moves of files and directories committing a transaction, planned
+// before any of them is made and written down, so that a commit that
+// fails or is interrupted halfway can be rolled back by undoing them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Journal {
+    // Top-level collections the transaction writes to.
+    collections: Vec<String>,
+    // Moves (from, to), in order.
+    moves: Vec<(PathBuf, PathBuf)>,
+    // Where the replaced and deleted files go until the commit is done.
+    backups: Vec<PathBuf>,
+    // Temporary files holding the new contents of records.
+    staged: Vec<PathBuf>,
+    // Whether all moves have been made.
+    committed: bool,
+}
+
+impl Journal {
+    // Plan replacing whatever is at `path` with what's at `source`, or
+    // removing it without a `source`, moving it to a backup first. The
+    // `exists` map tracks the paths the planned moves add or remove.
+    fn replace(
+        &mut self,
+        path: &Path,
+        source: Option<PathBuf>,
+        exists: &mut HashMap<PathBuf, bool>,
+    ) {
+        if *exists
+            .entry(path.to_path_buf())
+            .or_insert_with(|| path.exists())
+        {
+            let backup = sibling_path(path, "bak");
+
+            self.moves.push((path.to_path_buf(), backup.clone()));
+            self.backups.push(backup);
+        }
+
+        if let Some(source) = &source {
+            self.moves.push((source.clone(), path.to_path_buf()));
+            exists.insert(source.clone(), false);
+        }
+
+        exists.insert(path.to_path_buf(), source.is_some());
+    }
+
+    fn apply(&self) -> std::io::Result<()> {
+        for (from, to) in &self.moves {
+            fs::rename(from, to)?;
+        }
+
+        Ok(())
+    }
+
+    // Undo the moves that have been made, in reverse, and remove the
+    // temporary files.
+    fn roll_back(&self) -> std::io::Result<()> {
+        for (from, to) in self.moves.iter().rev() {
+            if to.exists() && !from.exists() {
+                fs::rename(to, from)?;
+            }
+        }
+
+        self.staged.iter().try_for_each(|path| remove_path(path))
+    }
+
+    // Remove the backups of a committed transaction.
+    fn finish(&self) -> std::io::Result<()> {
+        self.backups.iter().try_for_each(|path| remove_path(path))
+    }
+}
+
+fn is_markdown(record: &Record) -> bool {
+    str_ends_with_any(&record.file_name, Vec::from(["md", "markdown"]))
+}
+
+// Locks of the collection directories, shared by all `LocalProvider`s in
+// the process, so that no record is read while it is being written.
+static COLLECTION_LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<RwLock<()>>>>> = OnceLock::new();
+
+fn collection_lock(directory: PathBuf) -> Arc<RwLock<()>> {
+    let mut locks = COLLECTION_LOCKS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    locks.entry(directory).or_default().clone()
+}
+
+// Parse a file while holding the read lock of its collection.
+fn parse_file_locked(
+    lock: &RwLock<()>,
+    path: &Path,
+    root: &Path,
+    collection: &str,
+    with_body: bool,
+) -> Option<Record> {
+    let _guard = lock.read().unwrap_or_else(|e| e.into_inner());
+
+    parse_file(path, root, collection, with_body).ok()
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct LocalProvider {
     pub directory: String,
+    lock_wait: LockWait,
 }
 
-impl StoreProvider for LocalProvider {
-    fn retrieve(&self, name: &str) -> Vec<Record> {
-        let mut records = Vec::new();
-        let dir = fs::read_dir(format!("{}{}{}", self.directory, "/", name));
+impl LocalProvider {
+    pub fn new(directory: &str) -> LocalProvider {
+        LocalProvider {
+            directory: directory.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Wait for locks held by other processes as set by `lock_wait`.
+    pub fn with_lock_wait(self, lock_wait: LockWait) -> LocalProvider {
+        LocalProvider { lock_wait, ..self }
+    }
+
+    // Lock the top-level collection of the collection with the given `name`
+    // across processes: shared when writing single records, and exclusive
+    // when writing the whole collection at once.
+    fn lock_collection(&self, name: &str, shared: bool) -> Result<fs::File, SienaError> {
+        let top_level = name.split('/').next().unwrap_or(name);
+        let path = Path::new(&self.directory)
+            .join(LOCKS_DIR)
+            .join(format!("{}.lock", top_level));
+
+        lock_file(&path, shared, self.lock_wait)
+    }
+
+    // Lock the file of the `record` across processes. The lock of its
+    // collection has to be held already.
+    fn lock_record(&self, record: &Record) -> Result<fs::File, SienaError> {
+        let path = self.record_path(record);
+        let relative = path.strip_prefix(&self.directory).unwrap_or(&path);
+        let path = Path::new(&self.directory)
+            .join(LOCKS_DIR)
+            .join(format!("{}.lock", slash_path(relative)));
+
+        lock_file(&path, false, self.lock_wait)
+    }
+
+    // Nested collections share the lock of their top-level collection, as
+    // they share its files.
+    fn lock(&self, name: &str) -> Arc<RwLock<()>> {
+        let top_level = name.split('/').next().unwrap_or(name);
+
+        collection_lock(Path::new(&self.directory).join(top_level))
+    }
+
+    fn collection_dir(&self, name: &str) -> PathBuf {
+        Path::new(&self.directory).join(name)
+    }
+
+    // The directory of the `record`, if it's a page bundle, and still
+    // holds no other records that would go with it.
+    fn bundle_dir(&self, record: &Record) -> Option<PathBuf> {
+        let dir = self.collection_dir(&record.collection).join(&record.id);
+        let is_bundle = BUNDLE_INDEXES.contains(&record.file_name.as_str())
+            && record.path == format!("{}/{}", record.id, record.file_name)
+            && bundle_index(&dir).is_some();
+
+        match is_bundle {
+            true => Some(dir),
+            false => None,
+        }
+    }
+
+    // The file of the `record` within the store directory.
+    fn record_path(&self, record: &Record) -> PathBuf {
+        let relative = match record.path.is_empty() {
+            true => &record.file_name,
+            false => &record.path,
+        };
+
+        self.collection_dir(&record.collection).join(relative)
+    }
+
+    // Paths of the files of the collection with the given `name`, and of
+    // its subdirectories, that have a supported extension. Page bundles
+    // only give their index file.
+    fn files(&self, name: &str) -> impl Iterator<Item = PathBuf> {
+        let mut dirs = Vec::from([self.collection_dir(name)]);
+        let mut files = Vec::new();
+
+        while let Some(dir) = dirs.pop() {
+            for file in fs::read_dir(dir).into_iter().flatten().flatten() {
+                let file_path = file.path();
+
+                // Skip hidden directories, like backups of page bundles
+                if file_path.is_dir() && file.file_name().to_string_lossy().starts_with('.') {
+                    continue;
+                }
+
+                if file_path.is_dir() {
+                    match bundle_index(&file_path) {
+                        Some(index) => files.push(index),
+                        None => dirs.push(file_path),
+                    }
+
+                    continue;
+                }
+
+                // Skip files when parser does not match file extension
+                if is_record_file(&file_path) {
+                    files.push(file_path);
+                }
+            }
+        }
+
+        files.into_iter()
+    }
+
+    // Whether a record with the given `id` is stored in the collection with
+    // the given `name`, as a file or a page bundle. The paths in `exists`
+    // override what's on disk, for changes that are planned but not made.
+    fn is_taken(&self, name: &str, id: &str, exists: &HashMap<PathBuf, bool>) -> bool {
+        let dir = self.collection_dir(name);
+        let bundle = dir.join(id);
+        let bundle_taken = match exists.get(&bundle) {
+            Some(exists) => *exists,
+            None => bundle_index(&bundle).is_some(),
+        };
+
+        bundle_taken
+            || RECORD_EXTENSIONS
+                .iter()
+                .map(|ext| dir.join(format!("{}.{}", id, ext)))
+                .any(|path| exists.get(&path).copied().unwrap_or_else(|| path.is_file()))
+    }
+
+    // The file holding all records of the collection with the given `name`,
+    // if it's stored as a single file rather than as a directory.
+    fn collection_file(&self, name: &str) -> Option<PathBuf> {
+        let path = self.collection_dir(name);
+
+        if path.is_dir() {
+            return None;
+        }
 
-        if dir.is_err() {
-            return records;
+        ["yml", "yaml", "json", "jsonl"]
+            .iter()
+            .map(|ext| PathBuf::from(format!("{}.{}", path.display(), ext)))
+            .find(|path| path.is_file())
+    }
+
+    // Rewrite the single-file collection with the given `name` at `path`,
+    // replacing or adding the `updated` records, and leaving out the
+    // records with the `deleted` ids, after checking the `updated` records
+    // according to `mode`. Returns the file's new version.
+    fn rewrite_collection_file(
+        &self,
+        name: &str,
+        path: &Path,
+        updated: Vec<Record>,
+        deleted: &[String],
+        mode: WriteMode,
+    ) -> Result<String, SienaError> {
+        let _collection_lock = self.lock_collection(name, false)?;
+        let lock = self.lock(name);
+        let _guard = lock.write().unwrap_or_else(|e| e.into_inner());
+        let mut records = parse_collection_file(path, name)
+            .map_err(|e| SienaError::Io(format!("{}: {}", path.display(), e)))?;
+        let current = fs::read_to_string(path)
+            .ok()
+            .map(|contents| version(&contents));
+
+        if let Some(record) = updated
+            .iter()
+            .find(|record| mode == WriteMode::IfUnchanged && record.version != current)
+        {
+            return Err(SienaError::Conflict(record.id.clone()));
         }
 
-        for file in dir.unwrap() {
-            // Skip iteration when parser does not match file extension
-            let file_path = file.as_ref().unwrap().path();
-            let file_path_str = file_path.to_str().clone().unwrap();
-            let allowed_exts = Vec::from(["yml", "yaml", "md", "markdown"]);
+        if let Some(record) = updated
+            .iter()
+            .find(|record| mode == WriteMode::New && records.iter().any(|r| r.id == record.id))
+        {
+            return Err(SienaError::Exists(record.id.clone()));
+        }
+
+        records.retain(|record| !deleted.contains(&record.id));
+
+        for record in updated {
+            match records.iter_mut().find(|r| r.id == record.id) {
+                Some(existing) => *existing = record,
+                None => records.push(record),
+            }
+        }
+
+        write_collection_file(path, &records)
+    }
+
+    // Run `f` while holding exclusive locks on the top-level collections
+    // with the given `names`, across processes and within this one, taken
+    // in order so that concurrent commits can't deadlock.
+    fn with_collections_locked<T>(
+        &self,
+        names: &[String],
+        wait: LockWait,
+        f: impl FnOnce() -> Result<T, SienaError>,
+    ) -> Result<T, SienaError> {
+        let mut names = names.to_vec();
+
+        names.sort();
+        names.dedup();
+
+        let _files = names
+            .iter()
+            .map(|name| {
+                let path = Path::new(&self.directory)
+                    .join(LOCKS_DIR)
+                    .join(format!("{}.lock", name));
+
+                lock_file(&path, false, wait)
+            })
+            .collect::<Result<Vec<fs::File>, SienaError>>()?;
+        let locks: Vec<Arc<RwLock<()>>> = names.iter().map(|name| self.lock(name)).collect();
+        let _guards: Vec<_> = locks
+            .iter()
+            .map(|lock| lock.write().unwrap_or_else(|e| e.into_inner()))
+            .collect();
+
+        f()
+    }
+
+    // Plan the moves committing the `changes` in the `journal`, staging the
+    // new contents of records in temporary files. Nothing is moved yet.
+    // Fails with `SienaError::Conflict` when a record set only if unchanged
+    // is no longer at its version.
+    fn plan_commit(&self, changes: Vec<Change>, journal: &mut Journal) -> Result<(), SienaError> {
+        let mut exists: HashMap<PathBuf, bool> = HashMap::new();
+        let mut collection_files: Vec<(PathBuf, Vec<Record>)> = Vec::new();
+
+        for change in changes {
+            let collection = change.record().collection.clone();
+
+            // Single-file collections are written once, with all changes
+            if let Some(path) = self.collection_file(&collection) {
+                if let Change::SetIfUnchanged(record) = &change {
+                    if fs::read_to_string(&path).ok().map(|c| version(&c)) != record.version {
+                        return Err(SienaError::Conflict(record.id.clone()));
+                    }
+                }
+
+                let index = match collection_files.iter().position(|(p, _)| p == &path) {
+                    Some(index) => index,
+                    None => {
+                        let records = parse_collection_file(&path, &collection)
+                            .map_err(|e| SienaError::Io(format!("{}: {}", path.display(), e)))?;
+
+                        collection_files.push((path, records));
+                        collection_files.len() - 1
+                    }
+                };
+
+                if let Change::Rename(_, id) = &change {
+                    if collection_files[index].1.iter().any(|r| &r.id == id) {
+                        return Err(SienaError::Exists(id.clone()));
+                    }
+                }
 
-            if !str_ends_with_any(file_path_str, allowed_exts) {
+                change.apply(&mut collection_files[index].1);
                 continue;
             }
 
-            // If we made it this far, continue with parsing
-            if file.as_ref().is_ok() {
-                if let Ok(record) = parse_file(&file.unwrap(), &name) {
-                    records.push(record);
+            let check = matches!(change, Change::SetIfUnchanged(_));
+
+            match change {
+                Change::Set(record) | Change::SetIfUnchanged(record) => {
+                    let path = self.record_path(&record);
+                    let directory = path.parent().unwrap().to_path_buf();
+                    let existing = fs::read_to_string(&path).ok();
+
+                    if check && existing.as_deref().map(version) != record.version {
+                        return Err(SienaError::Conflict(record.id));
+                    }
+                    let contents =
+                        serialize_record(&record, existing.as_deref()).ok_or_else(|| {
+                            SienaError::Io(format!("Could not serialize {}", path.display()))
+                        })?;
+
+                    fs::create_dir_all(&directory).map_err(io_error(&directory))?;
+
+                    let temp = write_temp(&path, contents.as_bytes()).map_err(io_error(&path))?;
+
+                    journal.staged.push(temp.clone());
+                    journal.replace(&path, Some(temp), &mut exists);
+                }
+                Change::Delete(record) => {
+                    let path = self
+                        .bundle_dir(&record)
+                        .unwrap_or_else(|| self.record_path(&record));
+
+                    journal.replace(&path, None, &mut exists);
+                }
+                Change::Rename(record, id) => {
+                    if self.is_taken(&record.collection, &id, &exists) {
+                        return Err(SienaError::Exists(id));
+                    }
+
+                    let (from, to) = match self.bundle_dir(&record) {
+                        Some(dir) => (dir, self.collection_dir(&record.collection).join(&id)),
+                        None => (
+                            self.record_path(&record),
+                            self.record_path(&record.renamed(&id)),
+                        ),
+                    };
+
+                    if let Some(parent) = to.parent() {
+                        fs::create_dir_all(parent).map_err(io_error(parent))?;
+                    }
+
+                    journal.replace(&to, Some(from), &mut exists);
                 }
             }
         }
 
-        return records;
+        for (path, records) in collection_files {
+            let contents = serialize_collection(&path, &records)?;
+            let temp = write_temp(&path, contents.as_bytes()).map_err(io_error(&path))?;
+
+            journal.staged.push(temp.clone());
+            journal.replace(&path, Some(temp), &mut exists);
+        }
+
+        Ok(())
+    }
+
+    // Roll back the commits of transactions that were interrupted, by a
+    // crash for instance, and clean up after the ones that completed.
+    // Commits still in progress are left alone. This also happens before
+    // every commit.
+    pub fn recover(&self) -> Result<(), SienaError> {
+        let dir = Path::new(&self.directory).join(JOURNALS_DIR);
+
+        for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let journal: Journal = match fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str(&contents).ok())
+            {
+                Some(journal) => journal,
+                None => continue,
+            };
+
+            let result =
+                self.with_collections_locked(&journal.collections, LockWait::NoWait, || {
+                    match journal.committed {
+                        true => journal.finish(),
+                        false => journal.roll_back(),
+                    }
+                    .and_then(|_| fs::remove_file(&path))
+                    .map_err(io_error(&path))
+                });
+
+            match result {
+                Err(SienaError::LockTimeout(_)) => continue,
+                result => result?,
+            }
+        }
+
+        Ok(())
     }
 
-    fn set(&self, records: Vec<Record>, data: Vec<(&str, &RecordData)>) -> Vec<Record> {
+    // Set `data` in the `records` and write them, returning them with their
+    // new versions. Each record is checked according to `mode` while
+    // holding its locks, and the first failing check stops the write.
+    fn write(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+        mode: WriteMode,
+    ) -> Result<Vec<Record>, SienaError> {
         let mut updated_records: Vec<Record> = Vec::new();
+        let mut collection_files: HashMap<PathBuf, Vec<Record>> = HashMap::new();
 
         for mut record in records {
-            let directory = format!("{}/{}", self.directory, record.collection);
-
-            // Create dir if it doesnt exist
-            fs::create_dir_all(&directory).expect(&format!("Could not create {}", directory));
-
-            // Write to file
-            let file = fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(format!("{}/{}", directory, record.file_name))
-                .expect(&format!(
-                    "Could not write to file {}/{}",
-                    directory, record.file_name
-                ));
-
             for data_item in data.clone() {
                 record
                     .data
                     .insert(data_item.0.to_string(), data_item.1.clone());
             }
 
-            updated_records.push(record.clone());
+            if let Some(path) = self.collection_file(&record.collection) {
+                updated_records.push(record.clone());
+                collection_files.entry(path).or_default().push(record);
+                continue;
+            }
+
+            let file_path = self.record_path(&record);
+            let directory = file_path.parent().unwrap().to_path_buf();
+            let _collection_lock = self.lock_collection(&record.collection, true)?;
+            let _record_lock = self.lock_record(&record)?;
+            let lock = self.lock(&record.collection);
+            let _guard = lock.write().unwrap_or_else(|e| e.into_inner());
+            let existing = fs::read_to_string(&file_path).ok();
 
-            // yaml
-            if str_ends_with_any(record.file_name.as_ref(), Vec::from(["yml", "yaml"])) {
-                serde_yaml::to_writer(file, &record.data).expect(&format!(
-                    "Could not write to file {}/{}",
-                    directory, record.file_name
-                ));
+            if mode == WriteMode::IfUnchanged && existing.as_deref().map(version) != record.version
+            {
+                return Err(SienaError::Conflict(record.id));
             }
 
-            // frontmatter
-            if str_ends_with_any(record.file_name.as_ref(), Vec::from(["md", "markdown"])) {
-                let meta = record.data.clone();
+            if mode == WriteMode::New
+                && self.is_taken(&record.collection, &record.id, &HashMap::new())
+            {
+                return Err(SienaError::Exists(record.id));
+            }
+
+            let contents = serialize_record(&record, existing.as_deref()).ok_or_else(|| {
+                SienaError::Io(format!("Could not serialize {}", file_path.display()))
+            })?;
 
-                match record.data.get("content_raw").unwrap() {
-                    RecordData::Str(md) => {
-                        let fm = serde_frontmatter::serialize(meta, md).unwrap_or(String::from(""));
-                        let file_path = format!("{}/{}", directory, record.file_name);
+            // Create dir if it doesnt exist
+            fs::create_dir_all(&directory).map_err(io_error(&directory))?;
 
-                        fs::write(&file_path, fm)
-                            .expect(&format!("Could not write to {}", file_path));
+            match mode {
+                // Other processes that don't take the locks can still get
+                // in between, but never get their file overwritten
+                WriteMode::New => match write_new(&file_path, contents.as_bytes()) {
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                        return Err(SienaError::Exists(record.id));
                     }
-                    _ => (),
+                    result => result.map_err(io_error(&file_path))?,
+                },
+                _ => write_atomic(&file_path, contents.as_bytes()).map_err(io_error(&file_path))?,
+            }
+
+            record.version = Some(version(&contents));
+            updated_records.push(record);
+        }
+
+        for (path, records) in collection_files {
+            let name = records[0].collection.clone();
+            let ids: Vec<String> = records.iter().map(|record| record.id.clone()).collect();
+            let file_version = self.rewrite_collection_file(&name, &path, records, &[], mode)?;
+
+            for record in updated_records.iter_mut() {
+                if record.collection == name && ids.contains(&record.id) {
+                    record.version = Some(file_version.clone());
                 }
             }
         }
 
-        return updated_records;
+        Ok(updated_records)
+    }
+
+    // Read all records of the collection with the given `name`, leaving
+    // the Markdown body out of them unless `with_body` is set.
+    fn read(&self, name: &str, with_body: bool) -> Vec<Record> {
+        let lock = self.lock(name);
+
+        if let Some(path) = self.collection_file(name) {
+            let _guard = lock.read().unwrap_or_else(|e| e.into_inner());
+
+            return parse_collection_file(&path, name).unwrap_or_default();
+        }
+
+        let root = self.collection_dir(name);
+
+        self.files(name)
+            .filter_map(|path| parse_file_locked(&lock, &path, &root, name, with_body))
+            .collect()
+    }
+}
+
+impl StoreProvider for LocalProvider {
+    fn retrieve(&self, name: &str) -> Vec<Record> {
+        self.read(name, true)
+    }
+
+    fn stream(&self, name: &str) -> Box<dyn Iterator<Item = Record> + Send> {
+        if self.collection_file(name).is_some() {
+            return Box::new(self.read(name, true).into_iter());
+        }
+
+        let lock = self.lock(name);
+        let root = self.collection_dir(name);
+        let name = name.to_string();
+
+        Box::new(
+            self.files(&name)
+                .filter_map(move |path| parse_file_locked(&lock, &path, &root, &name, true)),
+        )
+    }
+
+    fn query(&self, query: &Query) -> Option<Vec<Record>> {
+        let body_keys = [frontmatter::CONTENT, frontmatter::CONTENT_RAW];
+
+        // When the query itself looks at the Markdown body, it's needed for
+        // every record, and when it doesn't return it, for none.
+        if body_keys.iter().any(|key| query.uses(key)) {
+            return Some(query.apply(self.read(&query.collection, true)));
+        }
+
+        if !body_keys.iter().any(|key| query.wants(key)) {
+            return Some(query.apply(self.read(&query.collection, false)));
+        }
+
+        // Otherwise, only parse the body of the records that made the cut.
+        let lock = self.lock(&query.collection);
+        let root = self.collection_dir(&query.collection);
+        let records = query
+            .evaluate(self.read(&query.collection, false))
+            .into_iter()
+            .map(|record| {
+                if !is_markdown(&record) {
+                    return record;
+                }
+
+                let path = self.record_path(&record);
+
+                parse_file_locked(&lock, &path, &root, &record.collection, true).unwrap_or(record)
+            })
+            .collect();
+
+        Some(query.project(records))
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.write(records, data, WriteMode::Overwrite)
+    }
+
+    // Versions are checked while holding the locks the write needs, so no
+    // other writer can get in between.
+    fn set_if_unchanged(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.write(records, data, WriteMode::IfUnchanged)
+    }
+
+    // Records are checked while holding the locks the write needs, and
+    // their files created only if there are none yet.
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.write(records, data, WriteMode::New)
     }
 
-    fn delete(&self, records: Vec<Record>) {
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
         for record in records {
-            let directory = format!("{}/{}", self.directory, record.collection);
-            let file = format!("{}/{}", directory, record.file_name);
+            if let Some(path) = self.collection_file(&record.collection) {
+                self.rewrite_collection_file(
+                    &record.collection,
+                    &path,
+                    Vec::new(),
+                    &[record.id],
+                    WriteMode::Overwrite,
+                )?;
+                continue;
+            }
+
+            let _collection_lock = self.lock_collection(&record.collection, true)?;
+            let _record_lock = self.lock_record(&record)?;
+            let lock = self.lock(&record.collection);
+            let _guard = lock.write().unwrap_or_else(|e| e.into_inner());
+
+            // Page bundles go together with their assets
+            if let Some(dir) = self.bundle_dir(&record) {
+                fs::remove_dir_all(&dir).map_err(io_error(&dir))?;
+                continue;
+            }
+
+            let file = self.record_path(&record);
+
+            fs::remove_file(&file).map_err(io_error(&file))?;
+        }
+
+        Ok(())
+    }
+
+    // All changes are planned and staged in temporary files first, then
+    // written down in a journal, and only then moved in place, undoing the
+    // moves made so far when one fails.
+    fn commit(&self, changes: Vec<Change>) -> Result<(), SienaError> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        self.recover()?;
+
+        let collections: Vec<String> = changes
+            .iter()
+            .map(|change| {
+                let name = &change.record().collection;
+
+                name.split('/').next().unwrap_or(name).to_string()
+            })
+            .collect();
+
+        self.with_collections_locked(&collections, self.lock_wait, || {
+            let mut journal = Journal {
+                collections: collections.clone(),
+                ..Default::default()
+            };
+
+            if let Err(e) = self.plan_commit(changes, &mut journal) {
+                let _ = journal.roll_back();
+                return Err(e);
+            }
+
+            let journal_dir = Path::new(&self.directory).join(JOURNALS_DIR);
+            let journal_path = sibling_path(&journal_dir.join("commit"), "json");
+            let write_journal = |journal: &Journal| {
+                fs::create_dir_all(&journal_dir)?;
+
+                let contents = serde_json::to_string(journal).map_err(std::io::Error::other)?;
 
-            fs::remove_file(file.clone()).expect(&format!("Cannot delete file: {}", file));
+                write_atomic(&journal_path, contents.as_bytes())
+            };
+
+            let result = write_journal(&journal)
+                .and_then(|_| journal.apply())
+                .and_then(|_| {
+                    journal.committed = true;
+                    write_journal(&journal)
+                });
+
+            if let Err(e) = result {
+                journal.committed = false;
+
+                return match journal.roll_back() {
+                    Ok(()) => {
+                        let _ = fs::remove_file(&journal_path);
+                        Err(io_error(&journal_path)(e))
+                    }
+                    // Leave the journal for `recover` to finish rolling back
+                    Err(_) => Err(io_error(&journal_path)(e)),
+                };
+            }
+
+            // The commit is done, so backups that can't be removed yet are
+            // left for `recover`
+            if journal.finish().is_ok() {
+                let _ = fs::remove_file(&journal_path);
+            }
+
+            Ok(())
+        })
+    }
+
+    // Records are moved in place, and page bundles as a whole, assets
+    // included.
+    fn rename(&self, record: Record, id: &str) -> Result<Record, SienaError> {
+        let renamed = record.renamed(id);
+
+        if let Some(path) = self.collection_file(&record.collection) {
+            let version = self.rewrite_collection_file(
+                &record.collection,
+                &path,
+                Vec::from([renamed.clone()]),
+                &[record.id],
+                WriteMode::New,
+            )?;
+
+            return Ok(Record {
+                version: Some(version),
+                ..renamed
+            });
+        }
+
+        let (from, to) = match self.bundle_dir(&record) {
+            Some(dir) => (dir, self.collection_dir(&record.collection).join(id)),
+            None => (self.record_path(&record), self.record_path(&renamed)),
+        };
+        let _collection_lock = self.lock_collection(&record.collection, true)?;
+        // Taken in order, so that renames the other way around can't deadlock
+        let mut locked = [&record, &renamed];
+
+        locked.sort_by_key(|record| self.record_path(record));
+
+        let _record_locks = locked
+            .iter()
+            .map(|record| self.lock_record(record))
+            .collect::<Result<Vec<fs::File>, SienaError>>()?;
+        let lock = self.lock(&record.collection);
+        let _guard = lock.write().unwrap_or_else(|e| e.into_inner());
+
+        if self.is_taken(&record.collection, id, &HashMap::new()) {
+            return Err(SienaError::Exists(id.to_string()));
+        }
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(io_error(parent))?;
         }
+
+        fs::rename(&from, &to).map_err(io_error(&from))?;
+
+        Ok(renamed)
     }
 }
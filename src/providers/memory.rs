@@ -0,0 +1,154 @@
+use crate::siena::{Record, RecordData, SienaError, StoreProvider};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+// Records of each collection, by id.
+type Collections = HashMap<String, HashMap<String, Record>>;
+
+// A provider keeping records in memory, for tests and ephemeral stores.
+// Clones share the same records.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryProvider {
+    collections: Arc<RwLock<Collections>>,
+}
+
+impl MemoryProvider {
+    pub fn new() -> MemoryProvider {
+        MemoryProvider::default()
+    }
+
+    // Seed the provider with `records`, each going to its own collection.
+    pub fn with_records(self, records: Vec<Record>) -> MemoryProvider {
+        for record in records {
+            self.insert(record);
+        }
+
+        self
+    }
+
+    // Seed the provider with a record in `collection` with the given `id`
+    // and (key, value) `data`.
+    pub fn with_record(
+        self,
+        collection: &str,
+        id: &str,
+        data: Vec<(&str, RecordData)>,
+    ) -> MemoryProvider {
+        self.insert(Record {
+            id: id.to_string(),
+            collection: collection.to_string(),
+            file_name: format!("{}.yml", id),
+            data: data
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+            ..Default::default()
+        });
+
+        self
+    }
+
+    // Insert the `record` into its collection, replacing any record with
+    // the same id.
+    pub fn insert(&self, record: Record) {
+        self.collections
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(record.collection.clone())
+            .or_default()
+            .insert(record.id.clone(), record);
+    }
+
+    // Remove all records from all collections.
+    pub fn clear(&self) {
+        self.collections
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+impl StoreProvider for MemoryProvider {
+    // Records are returned ordered by id.
+    fn retrieve(&self, name: &str) -> Vec<Record> {
+        let collections = self.collections.read().unwrap_or_else(|e| e.into_inner());
+        let mut records: Vec<Record> = collections
+            .get(name)
+            .map(|records| records.values().cloned().collect())
+            .unwrap_or_default();
+
+        records.sort_by(|a, b| a.id.cmp(&b.id));
+
+        records
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let mut updated_records: Vec<Record> = Vec::new();
+
+        for mut record in records {
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            self.insert(record.clone());
+            updated_records.push(record);
+        }
+
+        Ok(updated_records)
+    }
+
+    // Records are checked and inserted while holding the write lock.
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let mut collections = self.collections.write().unwrap_or_else(|e| e.into_inner());
+        let mut new_records: Vec<Record> = Vec::new();
+
+        for mut record in records {
+            if collections
+                .get(&record.collection)
+                .is_some_and(|collection| collection.contains_key(&record.id))
+                || new_records
+                    .iter()
+                    .any(|r| r.collection == record.collection && r.id == record.id)
+            {
+                return Err(SienaError::Exists(record.id));
+            }
+
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            new_records.push(record);
+        }
+
+        for record in &new_records {
+            collections
+                .entry(record.collection.clone())
+                .or_default()
+                .insert(record.id.clone(), record.clone());
+        }
+
+        Ok(new_records)
+    }
+
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
+        let mut collections = self.collections.write().unwrap_or_else(|e| e.into_inner());
+
+        for record in records {
+            if let Some(collection) = collections.get_mut(&record.collection) {
+                collection.remove(&record.id);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use crate::providers::memory::MemoryProvider;
+use crate::siena::{siena, Record, RecordData, RecordMap, SienaError, StoreProvider};
+
+fn provider() -> MemoryProvider {
+    MemoryProvider::new()
+        .with_record(
+            "posts",
+            "hello",
+            Vec::from([("title", RecordData::Str(String::from("Hello")))]),
+        )
+        .with_record(
+            "posts",
+            "bye",
+            Vec::from([("title", RecordData::Str(String::from("Bye")))]),
+        )
+}
+
+#[test]
+fn retrieve_test() {
+    let records = provider().retrieve("posts");
+
+    assert_eq!(
+        records.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(),
+        Vec::from(["bye", "hello"])
+    );
+    assert!(provider().retrieve("pages").is_empty());
+}
+
+#[test]
+fn set_test() {
+    let provider = provider();
+
+    siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .set(Vec::from([(
+            "status",
+            &RecordData::Str(String::from("draft")),
+        )]))
+        .unwrap();
+
+    siena(provider.clone())
+        .create("pages", "about")
+        .set(Vec::from([(
+            "title",
+            &RecordData::Str(String::from("About")),
+        )]))
+        .unwrap();
+
+    let post = siena(provider.clone())
+        .collection("posts")
+        .when_is("status", "draft")
+        .get_first()
+        .unwrap();
+
+    let page = siena(provider.clone())
+        .collection("pages")
+        .get_first()
+        .unwrap();
+
+    assert_eq!(
+        post,
+        Record {
+            id: String::from("hello"),
+            collection: String::from("posts"),
+            file_name: String::from("hello.yml"),
+            path: String::new(),
+            parent: None,
+            assets: Vec::new(),
+            version: None,
+            data: RecordMap::from([
+                (
+                    String::from("title"),
+                    RecordData::Str(String::from("Hello")),
+                ),
+                (
+                    String::from("status"),
+                    RecordData::Str(String::from("draft")),
+                ),
+            ]),
+        }
+    );
+    assert_eq!(page.id, "about");
+}
+
+#[test]
+fn delete_test() {
+    let provider = provider();
+
+    siena(provider.clone())
+        .collection("posts")
+        .when_is("title", "Bye")
+        .delete()
+        .unwrap();
+
+    let records = provider.retrieve("posts");
+
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].id, "hello");
+
+    provider.clear();
+
+    assert!(provider.retrieve("posts").is_empty());
+}
+
+#[test]
+fn rename_test() {
+    let provider = provider();
+
+    let renamed = siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .rename("hi")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(renamed.id, "hi");
+    assert_eq!(renamed.file_name, "hi.yml");
+    assert_eq!(
+        provider
+            .retrieve("posts")
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect::<Vec<&str>>(),
+        Vec::from(["bye", "hi"])
+    );
+}
+
+#[test]
+fn set_if_unchanged_test() {
+    let provider = provider();
+    let result = siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .set_if_unchanged(
+            None,
+            Vec::from([("title", &RecordData::Str(String::from("Hi")))]),
+        );
+
+    // Records aren't versioned, so a change can't be told apart
+    assert!(matches!(result, Err(SienaError::Unsupported(_))));
+    assert_eq!(
+        provider.retrieve("posts")[1].data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+}
+
+#[test]
+fn set_new_test() {
+    let provider = provider();
+    let title = RecordData::Str(String::from("Hi"));
+    let record = |id: &str| Record {
+        id: id.to_string(),
+        collection: String::from("posts"),
+        ..Default::default()
+    };
+
+    let result = provider.set_new(
+        Vec::from([record("new"), record("hello")]),
+        Vec::from([("title", &title)]),
+    );
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "hello"));
+    assert_eq!(provider.retrieve("posts").len(), 2);
+
+    provider
+        .set_new(Vec::from([record("new")]), Vec::from([("title", &title)]))
+        .unwrap();
+
+    assert_eq!(
+        provider.retrieve("posts")[2].data.get("title"),
+        Some(&title)
+    );
+}
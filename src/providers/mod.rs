@@ -1 +1,13 @@
+#[cfg(feature = "csv")]
+pub mod csv;
 pub mod local;
+pub mod memory;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[cfg(all(test, feature = "csv"))]
+mod csv_tests;
+#[cfg(test)]
+mod memory_tests;
+#[cfg(all(test, feature = "sqlite"))]
+mod sqlite_tests;
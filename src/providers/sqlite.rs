@@ -0,0 +1,274 @@
+use crate::{
+    query::{Query, QueryFilter},
+    siena::{Record, RecordData, RecordSortOrder, SienaError, StoreProvider},
+};
+use rusqlite::{params, params_from_iter, types::Value, Connection, Row};
+use std::sync::Mutex;
+
+// A provider storing each record as a row in a SQLite database, with its
+// data as JSON. Queries are translated to SQL where possible.
+pub struct SqliteProvider {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteProvider {
+    // Open, or create, the SQLite database at `path`.
+    pub fn open(path: &str) -> Result<SqliteProvider, rusqlite::Error> {
+        SqliteProvider::from_connection(Connection::open(path)?)
+    }
+
+    // Open a SQLite database that only lives in memory.
+    pub fn open_in_memory() -> Result<SqliteProvider, rusqlite::Error> {
+        SqliteProvider::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<SqliteProvider, rusqlite::Error> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS records (
+                collection TEXT NOT NULL,
+                id TEXT NOT NULL,
+                file_name TEXT NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (collection, id)
+            )",
+            [],
+        )?;
+
+        Ok(SqliteProvider {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    // Set `data` in the `records` and write them in a single transaction.
+    // With `new` set, fails with `SienaError::Exists` when one of them
+    // already has a row, writing none of them.
+    fn write(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+        new: bool,
+    ) -> Result<Vec<Record>, SienaError> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let transaction = connection
+            .transaction()
+            .map_err(db_error(String::from("Could not start a transaction")))?;
+        let mut updated_records: Vec<Record> = Vec::new();
+
+        for mut record in records {
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            let json = serde_json::to_string(&record.data)
+                .map_err(|e| SienaError::Io(format!("Could not serialize {}: {}", record.id, e)))?;
+
+            let sql = match new {
+                true => "INSERT INTO records (collection, id, file_name, data) VALUES (?, ?, ?, ?)",
+                false => {
+                    "INSERT INTO records (collection, id, file_name, data) VALUES (?, ?, ?, ?)
+                     ON CONFLICT (collection, id)
+                     DO UPDATE SET file_name = excluded.file_name, data = excluded.data"
+                }
+            };
+
+            match transaction.execute(
+                sql,
+                params![record.collection, record.id, record.file_name, json],
+            ) {
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                    if new && e.code == rusqlite::ErrorCode::ConstraintViolation =>
+                {
+                    return Err(SienaError::Exists(record.id));
+                }
+                result => result.map_err(db_error(format!("Could not write {}", record.id)))?,
+            };
+
+            updated_records.push(record);
+        }
+
+        transaction
+            .commit()
+            .map_err(db_error(String::from("Could not commit")))?;
+
+        Ok(updated_records)
+    }
+
+    fn select(&self, sql: &str, params: Vec<Value>) -> Result<Vec<Record>, rusqlite::Error> {
+        let connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map(params_from_iter(params), row_to_record)?;
+
+        rows.collect()
+    }
+}
+
+// Map a database error to a `SienaError::Io`, saying what was being done.
+fn db_error(doing: String) -> impl FnOnce(rusqlite::Error) -> SienaError {
+    move |e| SienaError::Io(format!("{}: {}", doing, e))
+}
+
+fn row_to_record(row: &Row) -> Result<Record, rusqlite::Error> {
+    let data: String = row.get(3)?;
+
+    Ok(Record {
+        id: row.get(0)?,
+        collection: row.get(1)?,
+        file_name: row.get(2)?,
+        data: serde_json::from_str(&data).unwrap_or_default(),
+        ..Default::default()
+    })
+}
+
+// The JSON path of a top-level `key`, for use with SQLite's JSON functions,
+// or `None` when the key can't be expressed as one.
+fn json_path(key: &str) -> Option<Value> {
+    if key.contains('"') {
+        return None;
+    }
+
+    Some(Value::Text(format!("$.\"{}\"", key)))
+}
+
+// Translate the `query` to a SQL statement with its parameters, or `None`
+// when some of it can't be expressed in SQL.
+fn translate(query: &Query) -> Option<(String, Vec<Value>)> {
+    let mut sql =
+        String::from("SELECT id, collection, file_name, data FROM records WHERE collection = ?");
+    let mut params = Vec::from([Value::Text(query.collection.clone())]);
+
+    for filter in &query.filters {
+        let path = json_path(filter.key())?;
+        let is_id = filter.key() == "id";
+
+        match filter {
+            QueryFilter::Is(_, value) => {
+                sql.push_str(" AND ((json_type(data, ?) = 'text' AND json_extract(data, ?) = ?)");
+                params.extend([path.clone(), path, Value::Text(value.clone())]);
+
+                if is_id {
+                    sql.push_str(" OR id = ?");
+                    params.push(Value::Text(value.clone()));
+                }
+
+                sql.push(')');
+            }
+            QueryFilter::IsNot(_, value) => {
+                sql.push_str(" AND ((json_type(data, ?) = 'text' AND json_extract(data, ?) != ?)");
+                params.extend([path.clone(), path, Value::Text(value.clone())]);
+
+                if is_id {
+                    sql.push_str(" OR id != ?");
+                    params.push(Value::Text(value.clone()));
+                }
+
+                sql.push(')');
+            }
+            QueryFilter::Has(_) => {
+                sql.push_str(" AND json_type(data, ?) IS NOT NULL");
+                params.push(path);
+            }
+            QueryFilter::HasNot(_) => {
+                sql.push_str(" AND json_type(data, ?) IS NULL");
+                params.push(path);
+            }
+            QueryFilter::Matches(_, _) => return None,
+        }
+    }
+
+    // The last sort is the primary one, and records lacking the key come
+    // last in ascending order and first in descending order.
+    let mut order_by: Vec<String> = Vec::new();
+
+    for (key, order) in query.sort.iter().rev() {
+        let direction = match order {
+            RecordSortOrder::Asc => "ASC",
+            RecordSortOrder::Desc => "DESC",
+            _ => return None,
+        };
+
+        if key == "id" {
+            order_by.push(format!("id {}", direction));
+            continue;
+        }
+
+        let path = json_path(key)?;
+
+        order_by.push(format!("json_extract(data, ?) IS NULL {}", direction));
+        order_by.push(format!("json_extract(data, ?) {}", direction));
+        params.extend([path.clone(), path]);
+    }
+
+    order_by.push(String::from("rowid"));
+    sql.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+
+    if query.limit.is_some() || query.offset > 0 {
+        sql.push_str(" LIMIT ? OFFSET ?");
+        // Past `i64::MAX` is as good as no limit, and an offset past all rows
+        params.push(Value::Integer(
+            query
+                .limit
+                .map_or(-1, |limit| i64::try_from(limit).unwrap_or(i64::MAX)),
+        ));
+        params.push(Value::Integer(
+            i64::try_from(query.offset).unwrap_or(i64::MAX),
+        ));
+    }
+
+    Some((sql, params))
+}
+
+impl StoreProvider for SqliteProvider {
+    // Records that can't be read, when the database is busy for instance,
+    // are left out.
+    fn retrieve(&self, name: &str) -> Vec<Record> {
+        self.select(
+            "SELECT id, collection, file_name, data FROM records WHERE collection = ? ORDER BY rowid",
+            Vec::from([Value::Text(name.to_string())]),
+        )
+        .unwrap_or_default()
+    }
+
+    fn query(&self, query: &Query) -> Option<Vec<Record>> {
+        let (sql, params) = translate(query)?;
+
+        Some(query.project(self.select(&sql, params).ok()?))
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.write(records, data, false)
+    }
+
+    // Records are inserted without replacing any, so the database itself
+    // tells when one exists.
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.write(records, data, true)
+    }
+
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let transaction = connection
+            .transaction()
+            .map_err(db_error(String::from("Could not start a transaction")))?;
+
+        for record in records {
+            transaction
+                .execute(
+                    "DELETE FROM records WHERE collection = ? AND id = ?",
+                    params![record.collection, record.id],
+                )
+                .map_err(db_error(format!("Could not delete {}", record.id)))?;
+        }
+
+        transaction
+            .commit()
+            .map_err(db_error(String::from("Could not commit")))
+    }
+}
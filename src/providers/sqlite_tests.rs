@@ -0,0 +1,239 @@
+use crate::providers::sqlite::SqliteProvider;
+use crate::query::{Query, QueryFilter};
+use crate::siena::{
+    siena, Record, RecordData, RecordMap, RecordSortOrder, SienaError, StoreProvider,
+};
+use crate::siena_tests::temp_store;
+
+fn records() -> Vec<Record> {
+    [
+        ("a", Some("Hello"), Some(3)),
+        ("b", Some("Bye"), None),
+        ("c", None, Some(1)),
+        ("d", Some("Hello"), Some(2)),
+        ("e", Some("Later"), None),
+    ]
+    .into_iter()
+    .map(|(id, title, order)| {
+        let mut data = RecordMap::new();
+
+        if let Some(title) = title {
+            data.insert(String::from("title"), RecordData::Str(title.to_string()));
+        }
+
+        if let Some(order) = order {
+            data.insert(String::from("order"), RecordData::Num(order));
+        }
+
+        Record {
+            id: id.to_string(),
+            collection: String::from("posts"),
+            file_name: format!("{}.yml", id),
+            path: String::new(),
+            parent: None,
+            assets: Vec::new(),
+            version: None,
+            data,
+        }
+    })
+    .collect()
+}
+
+fn provider() -> SqliteProvider {
+    let provider = SqliteProvider::open_in_memory().unwrap();
+
+    provider.set(records(), Vec::new()).unwrap();
+    provider
+}
+
+fn ids(records: Vec<Record>) -> Vec<String> {
+    records.into_iter().map(|r| r.id).collect()
+}
+
+#[test]
+fn query_test() {
+    let provider = provider();
+    let queries = [
+        Query {
+            filters: Vec::from([QueryFilter::Is(
+                String::from("title"),
+                String::from("Hello"),
+            )]),
+            ..Query::new("posts")
+        },
+        Query {
+            filters: Vec::from([
+                QueryFilter::IsNot(String::from("title"), String::from("Hello")),
+                QueryFilter::Has(String::from("title")),
+            ]),
+            ..Query::new("posts")
+        },
+        Query {
+            filters: Vec::from([QueryFilter::Is(String::from("id"), String::from("c"))]),
+            ..Query::new("posts")
+        },
+        Query {
+            filters: Vec::from([QueryFilter::HasNot(String::from("order"))]),
+            ..Query::new("posts")
+        },
+        Query {
+            sort: Vec::from([(String::from("order"), RecordSortOrder::Asc)]),
+            ..Query::new("posts")
+        },
+        Query {
+            sort: Vec::from([
+                (String::from("order"), RecordSortOrder::Desc),
+                (String::from("title"), RecordSortOrder::Asc),
+            ]),
+            ..Query::new("posts")
+        },
+        Query {
+            sort: Vec::from([(String::from("id"), RecordSortOrder::Desc)]),
+            offset: 1,
+            limit: Some(2),
+            ..Query::new("posts")
+        },
+        Query {
+            offset: 3,
+            ..Query::new("posts")
+        },
+        Query {
+            offset: usize::MAX,
+            limit: Some(usize::MAX),
+            ..Query::new("posts")
+        },
+    ];
+
+    for query in queries {
+        assert_eq!(
+            ids(provider.query(&query).unwrap()),
+            ids(query.apply(records())),
+            "{:?}",
+            query
+        );
+    }
+}
+
+#[test]
+fn query_fallback_test() {
+    let provider = provider();
+    let query = Query {
+        filters: Vec::from([QueryFilter::Matches(
+            String::from("title"),
+            regex::Regex::new("^H").unwrap(),
+        )]),
+        ..Query::new("posts")
+    };
+
+    assert!(provider.query(&query).is_none());
+
+    let records = siena(provider)
+        .collection("posts")
+        .when_matches("title", "^H")
+        .get_all();
+
+    assert_eq!(ids(records), Vec::from(["a", "d"]));
+}
+
+#[test]
+fn set_delete_test() {
+    let provider = provider();
+
+    provider
+        .set(
+            provider.retrieve("posts").into_iter().take(1).collect(),
+            Vec::from([("title", &RecordData::Str(String::from("Hi")))]),
+        )
+        .unwrap();
+    provider
+        .delete(provider.retrieve("posts").into_iter().skip(3).collect())
+        .unwrap();
+
+    let records = provider.retrieve("posts");
+
+    assert_eq!(ids(records.clone()), Vec::from(["a", "b", "c"]));
+    assert_eq!(
+        records[0].data.get("title"),
+        Some(&RecordData::Str(String::from("Hi")))
+    );
+    assert!(provider.retrieve("pages").is_empty());
+}
+
+#[test]
+fn set_new_test() {
+    let provider = provider();
+    let mut new = records().remove(0);
+
+    new.id = String::from("f");
+
+    let result = provider.set_new(
+        Vec::from([new.clone(), records().remove(0)]),
+        Vec::from([("title", &RecordData::Str(String::from("Hi")))]),
+    );
+
+    // Nothing is written when one of the records exists
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "a"));
+    assert_eq!(
+        ids(provider.retrieve("posts")),
+        Vec::from(["a", "b", "c", "d", "e"])
+    );
+    assert_eq!(
+        provider.retrieve("posts")[0].data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+
+    provider.set_new(Vec::from([new]), Vec::new()).unwrap();
+
+    assert_eq!(
+        ids(provider.retrieve("posts")),
+        Vec::from(["a", "b", "c", "d", "e", "f"])
+    );
+}
+
+#[test]
+fn database_error_test() {
+    let path = format!("{}/store.db", temp_store("sqlite-error", &[]));
+    let provider = SqliteProvider::open(&path).unwrap();
+
+    provider.set(records(), Vec::new()).unwrap();
+
+    // Another connection breaking the database from under the provider
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute("DROP TABLE records", [])
+        .unwrap();
+
+    let result = provider.set(records(), Vec::new());
+
+    assert!(matches!(result, Err(SienaError::Io(_))));
+    assert!(matches!(provider.delete(records()), Err(SienaError::Io(_))));
+    assert!(provider.retrieve("posts").is_empty());
+    assert!(provider.query(&Query::new("posts")).is_none());
+}
+
+#[test]
+fn delete_failing_test() {
+    let path = format!("{}/store.db", temp_store("sqlite-delete", &[]));
+    let provider = SqliteProvider::open(&path).unwrap();
+
+    provider.set(records(), Vec::new()).unwrap();
+
+    // Another connection keeping one of the records from being deleted
+    rusqlite::Connection::open(&path)
+        .unwrap()
+        .execute(
+            "CREATE TRIGGER keep BEFORE DELETE ON records WHEN old.id = 'c'
+             BEGIN SELECT RAISE(ABORT, 'kept'); END",
+            [],
+        )
+        .unwrap();
+
+    let result = provider.delete(records());
+
+    // None of the records are deleted then
+    assert!(matches!(result, Err(SienaError::Io(_))));
+    assert_eq!(
+        ids(provider.retrieve("posts")),
+        Vec::from(["a", "b", "c", "d", "e"])
+    );
+}
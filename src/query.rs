@@ -0,0 +1,255 @@
+use crate::pagination::Bound;
+use crate::siena::{Record, RecordData, RecordSortOrder};
+use regex::Regex;
+use std::cmp::Ordering;
+
+// A condition on a record key, as added by the `when_*` methods.
+#[derive(Debug, Clone)]
+pub enum QueryFilter {
+    Is(String, String),
+    IsNot(String, String),
+    Has(String),
+    HasNot(String),
+    Matches(String, Regex),
+}
+
+impl QueryFilter {
+    // The record key the filter is about.
+    pub fn key(&self) -> &str {
+        match self {
+            QueryFilter::Is(key, _)
+            | QueryFilter::IsNot(key, _)
+            | QueryFilter::Has(key)
+            | QueryFilter::HasNot(key)
+            | QueryFilter::Matches(key, _) => key,
+        }
+    }
+
+    // Whether the `record` passes the filter.
+    pub fn matches(&self, record: &Record) -> bool {
+        match self {
+            QueryFilter::Is(key, value) => {
+                if key == "id" && &record.id == value {
+                    return true;
+                }
+
+                match record.data.get(key) {
+                    Some(RecordData::Str(val)) => val == value,
+                    _ => false,
+                }
+            }
+            QueryFilter::IsNot(key, value) => {
+                if key == "id" && &record.id != value {
+                    return true;
+                }
+
+                match record.data.get(key) {
+                    Some(RecordData::Str(val)) => val != value,
+                    _ => false,
+                }
+            }
+            QueryFilter::Has(key) => record.data.contains_key(key),
+            QueryFilter::HasNot(key) => !record.data.contains_key(key),
+            QueryFilter::Matches(key, re) => {
+                if key == "id" && re.is_match(record.id.as_str()) {
+                    return true;
+                }
+
+                match record.data.get(key) {
+                    Some(RecordData::Str(val)) => re.is_match(val.as_str()),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+// Compare two records by a value for a key in `RecordSortOrder`.
+pub fn compare(a: &Record, b: &Record, key: &str, order: &RecordSortOrder) -> Ordering {
+    if key == "id" {
+        return match order {
+            RecordSortOrder::Asc => a.id.cmp(&b.id),
+            RecordSortOrder::Desc => b.id.cmp(&a.id),
+            RecordSortOrder::CustomStr(f) => f(a.id.clone(), b.id.clone()),
+            _ => Ordering::Equal,
+        };
+    }
+
+    // Records lacking the key come last in ascending order, and first in
+    // descending order.
+    let (a, b) = match (a.data.get(key), b.data.get(key)) {
+        (Some(a), Some(b)) => (a, b),
+        (None, None) => return Ordering::Equal,
+        (a, _) => {
+            let missing = match a {
+                None => Ordering::Greater,
+                Some(_) => Ordering::Less,
+            };
+
+            return match order {
+                RecordSortOrder::Asc => missing,
+                RecordSortOrder::Desc => missing.reverse(),
+                _ => Ordering::Equal,
+            };
+        }
+    };
+
+    match (a, b) {
+        (RecordData::Str(a), RecordData::Str(b)) => match order {
+            RecordSortOrder::Asc => a.cmp(b),
+            RecordSortOrder::Desc => b.cmp(a),
+            RecordSortOrder::CustomStr(f) => f(a.clone(), b.clone()),
+            _ => Ordering::Equal,
+        },
+        (RecordData::Num(a), RecordData::Num(b)) => match order {
+            RecordSortOrder::Asc => a.cmp(b),
+            RecordSortOrder::Desc => b.cmp(a),
+            RecordSortOrder::CustomNum(f) => f(*a, *b),
+            _ => Ordering::Equal,
+        },
+        _ => Ordering::Equal,
+    }
+}
+
+// A description of a query against a single collection, which Siena offers
+// to `StoreProvider::query` so that it could be executed natively.
+//
+// The query is evaluated by first applying all `filters`, then sorting by
+// each entry in `sort` in order (so the last one is the primary sort, as with
+// chained `sort` calls), then skipping `offset` records and keeping at most
+// `limit` records. When `fields` is set, only those keys are needed in
+// the `data` of the resulting records.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub collection: String,
+    pub filters: Vec<QueryFilter>,
+    pub sort: Vec<(String, RecordSortOrder)>,
+    pub offset: usize,
+    pub limit: Option<usize>,
+    pub fields: Option<Vec<String>>,
+}
+
+impl Query {
+    pub fn new(collection: &str) -> Query {
+        Query {
+            collection: collection.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Whether the records resulting from the query need the given `key`.
+    pub fn wants(&self, key: &str) -> bool {
+        match &self.fields {
+            Some(fields) => fields.iter().any(|field| field == key),
+            None => true,
+        }
+    }
+
+    // Whether the filters or the sorting of the query use the given `key`.
+    pub fn uses(&self, key: &str) -> bool {
+        self.filters.iter().any(|filter| filter.key() == key)
+            || self.sort.iter().any(|(sort_key, _)| sort_key == key)
+    }
+
+    // Evaluate the query in memory on all the `records` of the collection,
+    // without projecting them to `fields`.
+    pub fn evaluate(&self, mut records: Vec<Record>) -> Vec<Record> {
+        records.retain(|r| self.filters.iter().all(|filter| filter.matches(r)));
+
+        for (key, order) in &self.sort {
+            records.sort_by(|a, b| compare(a, b, key, order));
+        }
+
+        let records = records.into_iter().skip(self.offset);
+
+        match self.limit {
+            Some(limit) => records.take(limit).collect(),
+            None => records.collect(),
+        }
+    }
+
+    // Keep only the `fields` of the query in the data of the `record`.
+    pub fn project_record(&self, mut record: Record) -> Record {
+        if let Some(fields) = &self.fields {
+            record.data.retain(|key, _| fields.contains(key));
+        }
+
+        record
+    }
+
+    // Keep only the `fields` of the query in the data of `records`.
+    pub fn project(&self, records: Vec<Record>) -> Vec<Record> {
+        match &self.fields {
+            Some(_) => records
+                .into_iter()
+                .map(|record| self.project_record(record))
+                .collect(),
+            None => records,
+        }
+    }
+
+    // Evaluate the query in memory on all the `records` of the collection.
+    pub fn apply(&self, records: Vec<Record>) -> Vec<Record> {
+        self.project(self.evaluate(records))
+    }
+}
+
+// A single query method call, kept in the order the calls were made.
+#[derive(Debug, Clone)]
+pub(crate) enum QueryStep {
+    Filter(QueryFilter),
+    Sort(String, RecordSortOrder),
+    Offset(usize),
+    Limit(usize),
+    Push(Record),
+    // Like `Push`, for a record that must not exist yet.
+    Create(Record),
+    // Keep the records after or before a cursor.
+    Bound(Bound),
+}
+
+impl QueryStep {
+    pub(crate) fn apply(&self, mut records: Vec<Record>) -> Vec<Record> {
+        match self {
+            QueryStep::Filter(filter) => records.retain(|r| filter.matches(r)),
+            QueryStep::Bound(bound) => records.retain(|r| bound.matches(r)),
+            QueryStep::Sort(key, order) => records.sort_by(|a, b| compare(a, b, key, order)),
+            QueryStep::Offset(offset) => {
+                records.drain(0..(*offset).min(records.len()));
+            }
+            QueryStep::Limit(limit) => records.truncate(*limit),
+            QueryStep::Push(record) | QueryStep::Create(record) => records.push(record.clone()),
+        }
+
+        records
+    }
+}
+
+// Build the `Query` for a `collection` out of the longest prefix of `steps`
+// that it can describe, returning it together with the rest of the steps,
+// which have to be applied in memory on its result.
+pub(crate) fn plan<'a>(collection: &str, steps: &'a [QueryStep]) -> (Query, &'a [QueryStep]) {
+    let mut query = Query::new(collection);
+    let mut paged = false;
+
+    for (i, step) in steps.iter().enumerate() {
+        match step {
+            // Filtering and sorting are interchangeable with each other, but not
+            // with offsetting and limiting.
+            QueryStep::Filter(filter) if !paged => query.filters.push(filter.clone()),
+            QueryStep::Sort(key, order) if !paged => query.sort.push((key.clone(), order.clone())),
+            QueryStep::Offset(offset) => {
+                paged = true;
+                query.offset = query.offset.saturating_add(*offset);
+                query.limit = query.limit.map(|limit| limit.saturating_sub(*offset));
+            }
+            QueryStep::Limit(limit) => {
+                paged = true;
+                query.limit = Some(query.limit.map_or(*limit, |l| l.min(*limit)));
+            }
+            _ => return (query, &steps[i..]),
+        }
+    }
+
+    (query, &[])
+}
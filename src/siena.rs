@@ -1,7 +1,19 @@
+use crate::frontmatter;
+use crate::id::IdStrategy;
+use crate::pagination::{self, Bound, CursorBound, CursorPage, Page};
+use crate::query::{self, Query, QueryFilter, QueryStep};
+use crate::transaction::{Change, Transaction};
+use indexmap::IndexMap;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
+use std::sync::Arc;
 use std::{cmp::Ordering, collections::HashMap};
+use thiserror::Error;
+
+// The data of a record, keeping its keys in the order they were read in,
+// or inserted.
+pub type RecordMap = IndexMap<String, RecordData>;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
@@ -9,8 +21,9 @@ pub enum RecordData {
     Str(String),
     Num(usize),
     Bool(bool),
-    Map(HashMap<String, RecordData>),
+    Map(RecordMap),
     Vec(Vec<RecordData>),
+    Null,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
@@ -18,10 +31,47 @@ pub struct Record {
     pub id: String,
     pub collection: String,
     pub file_name: String,
-    pub data: HashMap<String, RecordData>,
+    // The path of the record's file relative to its collection, and the
+    // path of the subdirectory holding it, for providers storing records
+    // in nested directories.
+    #[serde(default)]
+    pub path: String,
+    pub parent: Option<String>,
+    // Other files belonging to the record, relative to its directory, for
+    // providers storing records as page bundles.
+    #[serde(default)]
+    pub assets: Vec<String>,
+    // A token identifying the stored version of the record, which changes
+    // whenever the record does, for providers tracking versions.
+    pub version: Option<String>,
+    pub data: RecordMap,
 }
 
-#[derive(Debug)]
+impl Record {
+    // A copy of the record with a new `id`, with its file name and path
+    // following suit.
+    pub fn renamed(&self, id: &str) -> Record {
+        let rename = |name: &str| match name.strip_prefix(self.id.as_str()) {
+            Some(rest) => format!("{}{}", id, rest),
+            None => name.to_string(),
+        };
+        let path = rename(&self.path);
+        let file_name = match path.is_empty() {
+            true => rename(&self.file_name),
+            false => path.rsplit('/').next().unwrap_or(&path).to_string(),
+        };
+
+        Record {
+            id: id.to_string(),
+            file_name,
+            parent: id.rsplit_once('/').map(|(parent, _)| parent.to_string()),
+            path,
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum RecordSortOrder {
     Asc,
     Desc,
@@ -29,10 +79,173 @@ pub enum RecordSortOrder {
     CustomNum(fn(usize, usize) -> Ordering),
 }
 
-pub trait StoreProvider {
+// Declares that the value of `key` in records of `collection` holds the
+// id (or a list of ids) of records in the `target` collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordReference {
+    pub collection: String,
+    pub key: String,
+    pub target: String,
+}
+
+// What to do with records referencing the records being deleted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeleteMode {
+    // Refuse to delete when any other record references the deleted ones.
+    Restrict,
+    // Delete the referencing records as well, recursively.
+    Cascade,
+    // Set the referencing fields to null, or remove the id from a list.
+    Nullify,
+}
+
+// The file format of a record, picking the extension of the file `create`
+// gives it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    #[default]
+    Yaml,
+    Markdown,
+    Json,
+    Toml,
+}
+
+impl RecordFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Yaml => "yml",
+            RecordFormat::Markdown => "md",
+            RecordFormat::Json => "json",
+            RecordFormat::Toml => "toml",
+        }
+    }
+}
+
+// A record that holds a reference to one of the records being deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockingRecord {
+    pub collection: String,
+    pub id: String,
+    pub key: String,
+}
+
+#[derive(Error, Debug)]
+pub enum SienaError {
+    #[error("Records are still referenced by {} other record(s)", .0.len())]
+    Referenced(Vec<BlockingRecord>),
+    #[error("Invalid cursor: {0}")]
+    InvalidCursor(String),
+    #[error("Could not acquire the lock on {0}")]
+    LockTimeout(String),
+    #[error("IO error: {0}")]
+    Io(String),
+    #[error("Record {0} has changed since it was read")]
+    Conflict(String),
+    #[error("Record {0} already exists")]
+    Exists(String),
+    #[error("Could not generate an id for a new record in {0}")]
+    NoId(String),
+    #[error("{0} is not supported by this provider")]
+    Unsupported(String),
+}
+
+pub trait StoreProvider: Send + Sync {
     fn retrieve(&self, name: &str) -> Vec<Record>;
-    fn set(&self, records: Vec<Record>, data: Vec<(&str, &RecordData)>) -> Vec<Record>;
-    fn delete(&self, records: Vec<Record>);
+
+    // Execute the `query` natively. Providers that can't, or only can for
+    // some queries, return `None`, in which case Siena evaluates the query
+    // in memory on the result of `retrieve`.
+    fn query(&self, _query: &Query) -> Option<Vec<Record>> {
+        None
+    }
+
+    // Yield the records of the collection with the given `name` one at a
+    // time. Providers that can read records lazily should implement this,
+    // as the default loads the whole collection via `retrieve`.
+    fn stream(&self, name: &str) -> Box<dyn Iterator<Item = Record> + Send> {
+        Box::new(self.retrieve(name).into_iter())
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError>;
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError>;
+
+    // Like `set`, but fails with `SienaError::Conflict` when a record's
+    // stored version is no longer the `version` it was read with. Only
+    // providers tracking versions can tell, so the default fails with
+    // `SienaError::Unsupported`. Versions must be checked and records
+    // written at once, with no other writer in between.
+    fn set_if_unchanged(
+        &self,
+        _records: Vec<Record>,
+        _data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        Err(SienaError::Unsupported(String::from("set_if_unchanged")))
+    }
+
+    // Like `set`, but for records that must not exist yet, failing with
+    // `SienaError::Exists` when one does. The default checks with freshly
+    // retrieved records before writing, so providers should implement this
+    // to check and write at once, with no other writer in between.
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        for record in &records {
+            if self
+                .retrieve(&record.collection)
+                .iter()
+                .any(|r| r.id == record.id)
+            {
+                return Err(SienaError::Exists(record.id.clone()));
+            }
+        }
+
+        self.set(records, data)
+    }
+
+    // Give the `record` a new `id`, failing with `SienaError::Exists` when
+    // another record has it. The default writes the record under the new
+    // id and then deletes the old one, so providers that can move records
+    // in place should implement this.
+    fn rename(&self, record: Record, id: &str) -> Result<Record, SienaError> {
+        if self.retrieve(&record.collection).iter().any(|r| r.id == id) {
+            return Err(SienaError::Exists(id.to_string()));
+        }
+
+        let renamed = record.renamed(id);
+        let renamed = self.set(Vec::from([renamed]), Vec::new())?.remove(0);
+
+        self.delete(Vec::from([record]))?;
+        Ok(renamed)
+    }
+
+    // Apply the `changes` staged by a transaction, in order, so that either
+    // all or none of them persist. The default applies them one by one, and
+    // can't undo the ones applied before a failing one, so providers that
+    // can roll back should implement this.
+    fn commit(&self, changes: Vec<Change>) -> Result<(), SienaError> {
+        for change in changes {
+            match change {
+                Change::Set(record) => {
+                    self.set(Vec::from([record]), Vec::new())?;
+                }
+                Change::SetIfUnchanged(record) => {
+                    self.set_if_unchanged(Vec::from([record]), Vec::new())?;
+                }
+                Change::Delete(record) => self.delete(Vec::from([record]))?,
+                Change::Rename(record, id) => {
+                    self.rename(record, &id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Debug for dyn StoreProvider {
@@ -41,240 +254,751 @@ impl Debug for dyn StoreProvider {
     }
 }
 
+// A store is cheap to clone, as clones share the same provider. It is also
+// `Send` and `Sync`, so it can be kept in shared application state and
+// cloned for every query.
 #[derive(Debug)]
-pub struct Siena {
-    pub(crate) records: Vec<Record>,
-    pub(crate) provider: Box<dyn StoreProvider>,
+pub struct Siena<P: ?Sized = dyn StoreProvider> {
+    pub(crate) provider: Arc<P>,
+    pub(crate) collection: Option<String>,
+    pub(crate) steps: Vec<QueryStep>,
+    pub(crate) fields: Option<Vec<String>>,
+    pub(crate) references: Vec<RecordReference>,
+    pub(crate) formats: HashMap<String, RecordFormat>,
+    pub(crate) ids: HashMap<String, IdStrategy>,
+    pub(crate) cursor: Option<CursorBound>,
 }
 
-impl Siena {
+impl<P: ?Sized> Clone for Siena<P> {
+    fn clone(&self) -> Self {
+        Siena {
+            provider: self.provider.clone(),
+            collection: self.collection.clone(),
+            steps: self.steps.clone(),
+            fields: self.fields.clone(),
+            references: self.references.clone(),
+            formats: self.formats.clone(),
+            ids: self.ids.clone(),
+            cursor: self.cursor.clone(),
+        }
+    }
+}
+
+impl<P: ?Sized> Siena<P> {
+    pub(crate) fn new(provider: Arc<P>) -> Siena<P> {
+        Siena {
+            provider,
+            collection: None,
+            steps: vec![],
+            fields: None,
+            references: vec![],
+            formats: HashMap::new(),
+            ids: HashMap::new(),
+            cursor: None,
+        }
+    }
+
     // Fetch records from a collection with a given `name`. A collection
     // is a directory in your Store, and a record is a YAML file in that
     // directory.
-    pub fn collection(mut self, name: &str) -> Siena {
-        self.records = self.provider.retrieve(name);
+    pub fn collection(mut self, name: &str) -> Siena<P> {
+        self.collection = Some(name.to_string());
+        self.steps.clear();
 
         self
     }
 
     // Filter records based on value equality for a key.
-    pub fn when_is(mut self, key: &str, equals_value: &str) -> Siena {
-        self.records = self
-            .records
-            .into_iter()
-            .filter(|r| {
-                if key == "id" && r.id == equals_value {
-                    return true;
-                }
+    pub fn when_is(self, key: &str, equals_value: &str) -> Siena<P> {
+        self.filter(QueryFilter::Is(key.to_string(), equals_value.to_string()))
+    }
 
-                if !r.data.contains_key(key) {
-                    return false;
-                }
+    // Filter records based on value inequality for a key.
+    pub fn when_is_not(self, key: &str, equals_value: &str) -> Siena<P> {
+        self.filter(QueryFilter::IsNot(
+            key.to_string(),
+            equals_value.to_string(),
+        ))
+    }
 
-                return match r.data.get(key).unwrap() {
-                    RecordData::Str(val) => val == equals_value,
-                    _ => false,
-                };
-            })
-            .collect();
+    // Filter records based on key presence.
+    pub fn when_has(self, key: &str) -> Siena<P> {
+        self.filter(QueryFilter::Has(key.to_string()))
+    }
 
-        self
+    // Filter records based on key lack of presence.
+    pub fn when_has_not(self, key: &str) -> Siena<P> {
+        self.filter(QueryFilter::HasNot(key.to_string()))
     }
 
-    // Filter records based on value inequality for a key.
-    pub fn when_is_not(mut self, key: &str, equals_value: &str) -> Siena {
-        self.records = self
-            .records
-            .into_iter()
-            .filter(|r| {
-                if key == "id" && r.id != equals_value {
-                    return true;
-                }
+    // Filter records based on value matching a regex pattern for a key.
+    pub fn when_matches(self, key: &str, pattern: &str) -> Siena<P> {
+        let re = Regex::new(pattern).unwrap();
 
-                if !r.data.contains_key(key) {
-                    return false;
-                }
+        self.filter(QueryFilter::Matches(key.to_string(), re))
+    }
 
-                return match r.data.get(key).unwrap() {
-                    RecordData::Str(val) => val != equals_value,
-                    _ => false,
-                };
-            })
-            .collect();
+    fn filter(mut self, filter: QueryFilter) -> Siena<P> {
+        self.steps.push(QueryStep::Filter(filter));
 
         self
     }
 
-    // Filter records based on key presence.
-    pub fn when_has(mut self, key: &str) -> Siena {
-        self.records = self
-            .records
-            .into_iter()
-            .filter(|r| r.data.contains_key(key))
-            .collect();
+    // Sort records by a value for a key in `RecordSortOrder`.
+    pub fn sort(mut self, key: &str, order: RecordSortOrder) -> Siena<P> {
+        self.steps.push(QueryStep::Sort(key.to_string(), order));
 
         self
     }
 
-    // Filter records based on key lack of presence.
-    pub fn when_has_not(mut self, key: &str) -> Siena {
-        self.records = self
-            .records
-            .into_iter()
-            .filter(|r| !r.data.contains_key(key))
-            .collect();
+    // Limit records.
+    pub fn limit(mut self, limit: usize) -> Siena<P> {
+        self.steps.push(QueryStep::Limit(limit));
 
         self
     }
 
-    // Filter records based on value matching a regex pattern for a key.
-    pub fn when_matches(mut self, key: &str, pattern: &str) -> Siena {
-        let re = Regex::new(pattern).unwrap();
+    // Offset records.
+    pub fn offset(mut self, offset: usize) -> Siena<P> {
+        self.steps.push(QueryStep::Offset(offset));
 
-        self.records = self
-            .records
-            .into_iter()
-            .filter(|r| {
-                if key == "id" && re.is_match(r.id.as_str()) {
-                    return true;
-                }
+        self
+    }
 
-                if !r.data.contains_key(key) {
-                    return false;
-                }
+    // Paginate records. Pages start from 1, and pages past the last one
+    // are empty.
+    pub fn paginate(self, page: usize, limit: usize) -> Siena<P> {
+        self.offset(page.saturating_sub(1).saturating_mul(limit))
+            .limit(limit)
+    }
 
-                return match r.data.get(key).unwrap() {
-                    RecordData::Str(val) => re.is_match(val.as_str()),
-                    _ => false,
-                };
-            })
-            .collect();
+    // Only return records that come after the record the `cursor` points
+    // at, in the current sort order. Cursors are given by `get_page`, and
+    // an invalid one gives no records, or `SienaError::InvalidCursor` from
+    // the methods returning a `Result`.
+    pub fn after(mut self, cursor: &str) -> Siena<P> {
+        self.cursor = Some(CursorBound::After(cursor.to_string()));
 
         self
     }
 
-    // Sort records by a value for a key in `RecordSortOrder`.
-    pub fn sort(mut self, key: &str, order: RecordSortOrder) -> Siena {
-        self.records.sort_by(|a, b| {
-            if key == "id" {
-                return match order {
-                    RecordSortOrder::Asc => a.id.cmp(&b.id),
-                    RecordSortOrder::Desc => b.id.cmp(&a.id),
-                    RecordSortOrder::CustomStr(f) => f(a.clone().id, b.clone().id),
-                    _ => Ordering::Equal,
-                };
-            }
+    // Only return records that come before the record the `cursor` points
+    // at, in the current sort order, see `after`.
+    pub fn before(mut self, cursor: &str) -> Siena<P> {
+        self.cursor = Some(CursorBound::Before(cursor.to_string()));
 
-            if a.data.get(key).is_none() || b.data.get(key).is_none() {
-                return match order {
-                    RecordSortOrder::Asc => Ordering::Greater,
-                    RecordSortOrder::Desc => Ordering::Less,
-                    _ => Ordering::Equal,
-                };
-            }
-            return match (a.data.get(key).unwrap(), b.data.get(key).unwrap()) {
-                (RecordData::Str(a), RecordData::Str(b)) => {
-                    return match order {
-                        RecordSortOrder::Asc => a.cmp(b),
-                        RecordSortOrder::Desc => b.cmp(a),
-                        RecordSortOrder::CustomStr(f) => f(a.clone(), b.clone()),
-                        _ => Ordering::Equal,
-                    };
-                }
-                (RecordData::Num(a), RecordData::Num(b)) => {
-                    return match order {
-                        RecordSortOrder::Asc => a.cmp(b),
-                        RecordSortOrder::Desc => b.cmp(a),
-                        RecordSortOrder::CustomNum(f) => f(a.clone(), b.clone()),
-                        _ => Ordering::Equal,
-                    };
-                }
-                _ => Ordering::Equal,
-            };
+        self
+    }
+
+    // Only keep the given `keys` in the data of the records returned by
+    // `get_all`, `get_first` and `get_last`.
+    pub fn select(mut self, keys: Vec<&str>) -> Siena<P> {
+        self.fields = Some(keys.iter().map(|key| key.to_string()).collect());
+
+        self
+    }
+
+    // Declare that the value of `key` in records of `collection` holds the
+    // id, or a list of ids, of records in the `target` collection. The
+    // declarations are used by `delete_with` to keep references intact.
+    pub fn reference(mut self, collection: &str, key: &str, target: &str) -> Siena<P> {
+        self.references.push(RecordReference {
+            collection: collection.to_string(),
+            key: key.to_string(),
+            target: target.to_string(),
         });
 
         self
     }
 
-    // Limit records.
-    pub fn limit(mut self, limit: usize) -> Siena {
-        self.records.truncate(limit);
+    // Set the `format` records created in `collection` by `create` get,
+    // instead of YAML.
+    pub fn format(mut self, collection: &str, format: RecordFormat) -> Siena<P> {
+        self.formats.insert(collection.to_string(), format);
 
         self
     }
 
-    // Offset records.
-    pub fn offset(mut self, offset: usize) -> Siena {
-        if self.records.len() >= offset {
-            self.records.drain(0..offset);
-        } else {
-            self.records = Vec::new();
-        }
+    // Create a new record in a `collection` with the given `id`, in the
+    // format set for the collection via `format`, or YAML.
+    //
+    // Note: this alone does not persist the newly created record, and in
+    // order to do so you must also set some data via the `set` method.
+    //
+    // Also note: if the record with such an `id` in given `collection` already
+    // exists, `set` fails with `SienaError::Exists`. Use `upsert` to
+    // overwrite it instead.
+    pub fn create(self, collection: &str, id: &str) -> Siena<P> {
+        let format = self.formats.get(collection).copied().unwrap_or_default();
+
+        self.create_as(collection, id, format)
+    }
+
+    // Create a new record like `create` does, in the given `format`.
+    // Markdown records start out with an empty body.
+    pub fn create_as(mut self, collection: &str, id: &str, format: RecordFormat) -> Siena<P> {
+        let record = new_record(collection, id, format);
+
+        self.steps.push(QueryStep::Create(record));
 
         self
     }
 
-    // Paginate records.
-    pub fn paginate(self, page: usize, limit: usize) -> Siena {
-        self.offset((page - 1) * limit).limit(limit)
+    // Create a new record like `create` does, overwriting the record with
+    // such an `id` in the given `collection` if there already is one.
+    pub fn upsert(mut self, collection: &str, id: &str) -> Siena<P> {
+        let format = self.formats.get(collection).copied().unwrap_or_default();
+
+        self.steps
+            .push(QueryStep::Push(new_record(collection, id, format)));
+
+        self
+    }
+
+    // Set the `strategy` `insert` generates the ids of records created in
+    // `collection` with, instead of ULIDs.
+    pub fn id_strategy(mut self, collection: &str, strategy: IdStrategy) -> Siena<P> {
+        self.ids.insert(collection.to_string(), strategy);
+
+        self
+    }
+
+    // The new record `insert` creates in `collection` to hold `data`, with
+    // an id generated by the collection's strategy, avoiding the `existing`
+    // ids of the collection.
+    pub(crate) fn generated_record(
+        &self,
+        collection: &str,
+        data: &[(&str, &RecordData)],
+        existing: Vec<String>,
+    ) -> Result<Record, SienaError> {
+        let format = self.formats.get(collection).copied().unwrap_or_default();
+        let strategy = self.ids.get(collection).cloned().unwrap_or_default();
+        let values: RecordMap = data
+            .iter()
+            .map(|(key, value)| (key.to_string(), (*value).clone()))
+            .collect();
+        let id = strategy
+            .generate(&values, &existing)
+            .ok_or_else(|| SienaError::NoId(collection.to_string()))?;
+
+        Ok(new_record(collection, &id, format))
+    }
+
+    // The records added by `create`, which must not exist yet, along with
+    // the queries finding them.
+    pub(crate) fn created(&self) -> Vec<(&Record, Query)> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                QueryStep::Create(record) => Some((
+                    record,
+                    Query {
+                        filters: Vec::from([QueryFilter::Is(
+                            String::from("id"),
+                            record.id.clone(),
+                        )]),
+                        fields: Some(Vec::new()),
+                        ..Query::new(&record.collection)
+                    },
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Split the `records` queried into the ones added by `create`, and the
+    // others.
+    pub(crate) fn split_created(&self, records: Vec<Record>) -> (Vec<Record>, Vec<Record>) {
+        let created = self.created();
+
+        records.into_iter().partition(|record| {
+            created
+                .iter()
+                .any(|(r, _)| r.collection == record.collection && r.id == record.id)
+        })
+    }
+
+    // The keys given to `sort`, in the order they were given.
+    pub(crate) fn sort_keys(&self) -> Vec<(String, RecordSortOrder)> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step {
+                QueryStep::Sort(key, order) => Some((key.clone(), order.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The steps of the query, led by the bound of the cursor given to
+    // `after` or `before`, which keeps only the records on its side of it.
+    pub(crate) fn bounded_steps(&self) -> Result<Vec<QueryStep>, SienaError> {
+        let mut steps = self.steps.clone();
+
+        if let Some(cursor) = &self.cursor {
+            steps.insert(0, QueryStep::Bound(Bound::new(cursor, &self.sort_keys())?));
+        }
+
+        Ok(steps)
+    }
+
+    // The query for the given `steps` to offer the provider, projected to
+    // `fields` when possible, along with the steps that have to be applied
+    // in memory on its result.
+    pub(crate) fn plan<'a>(
+        &self,
+        steps: &'a [QueryStep],
+        fields: Option<Vec<String>>,
+    ) -> (Option<Query>, &'a [QueryStep]) {
+        match &self.collection {
+            Some(collection) => {
+                let (mut query, steps) = query::plan(collection, steps);
+
+                // Keys left out by the projection could still be needed by the
+                // steps that follow, so only project right away without them.
+                if steps.is_empty() {
+                    query.fields = fields;
+                }
+
+                (Some(query), steps)
+            }
+            None => (None, steps),
+        }
+    }
+
+    // Apply the `steps` left over from `plan` on the `records` resulting
+    // from its query, and project them to `fields`.
+    pub(crate) fn finish(
+        &self,
+        records: Vec<Record>,
+        steps: &[QueryStep],
+        fields: Option<Vec<String>>,
+    ) -> Vec<Record> {
+        let records = steps
+            .iter()
+            .fold(records, |records, step| step.apply(records));
+
+        Query {
+            fields,
+            ..Default::default()
+        }
+        .project(records)
+    }
+}
+
+impl Siena {
+    // Run the query, offering as much of it as possible to the provider and
+    // evaluating the rest in memory, projecting the result to `fields`.
+    fn fetch(&self, fields: Option<Vec<String>>) -> Result<Vec<Record>, SienaError> {
+        let steps = self.bounded_steps()?;
+        let (query, steps) = self.plan(&steps, fields.clone());
+        let records = match query {
+            Some(query) => match self.provider.query(&query) {
+                Some(records) => records,
+                None => query.apply(self.provider.retrieve(&query.collection)),
+            },
+            None => Vec::new(),
+        };
+
+        Ok(self.finish(records, steps, fields))
     }
 
     // Get all records.
     pub fn get_all(self) -> Vec<Record> {
-        self.records
+        self.fetch(self.fields.clone()).unwrap_or_default()
     }
 
-    // Get first record.
-    pub fn get_first(self) -> Option<Record> {
-        if self.records.first().is_some() {
-            return Some(self.records.first().unwrap().clone());
+    // Get all records as an iterator, which reads them from the provider
+    // one at a time and applies filters, offsets and limits on the fly.
+    // Sorting needs all the records in memory at the point it is applied.
+    pub fn iter(self) -> Box<dyn Iterator<Item = Record> + Send> {
+        let records: Box<dyn Iterator<Item = Record> + Send> = match &self.collection {
+            Some(collection) => self.provider.stream(collection),
+            None => Box::new(std::iter::empty()),
+        };
+
+        let steps = match self.bounded_steps() {
+            Ok(steps) => steps,
+            Err(_) => return Box::new(std::iter::empty()),
+        };
+
+        let records = steps.into_iter().fold(records, |records, step| {
+            let records: Box<dyn Iterator<Item = Record> + Send> = match step {
+                QueryStep::Filter(filter) => Box::new(records.filter(move |r| filter.matches(r))),
+                QueryStep::Bound(bound) => Box::new(records.filter(move |r| bound.matches(r))),
+                QueryStep::Sort(_, _) => Box::new(step.apply(records.collect()).into_iter()),
+                QueryStep::Offset(offset) => Box::new(records.skip(offset)),
+                QueryStep::Limit(limit) => Box::new(records.take(limit)),
+                QueryStep::Push(record) | QueryStep::Create(record) => {
+                    Box::new(records.chain(std::iter::once(record)))
+                }
+            };
+
+            records
+        });
+
+        let projection = Query {
+            fields: self.fields,
+            ..Default::default()
+        };
+
+        Box::new(records.map(move |record| projection.project_record(record)))
+    }
+
+    // Get the given `page` of `per_page` records, together with the total
+    // number of records and pages, and the previous and next page numbers.
+    pub fn paginate_with_meta(self, page: usize, per_page: usize) -> Page {
+        let mut page = pagination::page(self.fetch(None).unwrap_or_default(), page, per_page);
+
+        page.records = Query {
+            fields: self.fields,
+            ..Default::default()
         }
+        .project(page.records);
 
-        None
+        page
+    }
+
+    // Get a page of at most `limit` records, right after or before the
+    // cursor given to `after` or `before`, or the first page otherwise.
+    // Records are ordered by the sort keys, and then by id, so that pages
+    // stay stable when records are added or removed between requests.
+    pub fn get_page(mut self, limit: usize) -> Result<CursorPage, SienaError> {
+        let sort = self.sort_keys();
+        // The page is cut out of all the records, to tell if there are more
+        // before and after it.
+        let cursor = self.cursor.take();
+        let mut page = pagination::cursor_page(self.fetch(None)?, &sort, cursor.as_ref(), limit)?;
+
+        page.records = Query {
+            fields: self.fields,
+            ..Default::default()
+        }
+        .project(page.records);
+
+        Ok(page)
+    }
+
+    // Get first record.
+    pub fn get_first(self) -> Option<Record> {
+        self.limit(1).get_all().into_iter().next()
     }
 
     // Get last record.
     pub fn get_last(self) -> Option<Record> {
-        if self.records.last().is_some() {
-            return Some(self.records.last().unwrap().clone());
+        self.get_all().pop()
+    }
+
+    // Run `query`, on the provider or in memory.
+    fn find(&self, query: &Query) -> Vec<Record> {
+        match self.provider.query(query) {
+            Some(records) => records,
+            None => query.apply(self.provider.retrieve(&query.collection)),
         }
+    }
 
-        None
+    // Fail with `SienaError::Exists` if a record added by `create` already
+    // exists.
+    fn ensure_new(&self) -> Result<(), SienaError> {
+        for (record, query) in self.created() {
+            if self.find(&query).iter().any(|r| r.id == record.id) {
+                return Err(SienaError::Exists(record.id.clone()));
+            }
+        }
+
+        Ok(())
     }
 
     // Set a Vector of tuples (key, value) in all records queried,
     // and persist them on file.
-    pub fn set(self, data: Vec<(&str, &RecordData)>) {
-        self.provider.set(self.records.clone(), data);
+    pub fn set(self, data: Vec<(&str, &RecordData)>) -> Result<(), SienaError> {
+        self.ensure_new()?;
+
+        let (created, records) = self.split_created(self.fetch(None)?);
+
+        self.provider.set_new(created, data.clone())?;
+        self.provider.set(records, data)?;
+
+        Ok(())
+    }
+
+    // Set a Vector of tuples (key, value) in the first record queried, but
+    // only if it's still at the given `version`, the one it was read with,
+    // that is, if it hasn't changed since. Returns the updated record with
+    // its new version, or `None` if there was no record to update, and
+    // fails with `SienaError::Conflict` if the record has changed.
+    pub fn set_if_unchanged(
+        self,
+        version: Option<&str>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Option<Record>, SienaError> {
+        self.ensure_new()?;
+
+        match self.fetch(None)?.into_iter().next() {
+            Some(mut record) => {
+                record.version = version.map(|version| version.to_string());
+
+                Ok(self
+                    .provider
+                    .set_if_unchanged(Vec::from([record]), data)?
+                    .into_iter()
+                    .next())
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Create a new record in a `collection` holding `data`, with an id
+    // generated by the strategy set for the collection via `id_strategy`,
+    // and persist it. Ids that are already taken, even by records another
+    // writer just created, get `-2`, `-3` and so on added to them. Returns the new record, or fails with
+    // `SienaError::NoId` when the strategy has nothing to generate an id
+    // from.
+    pub fn insert(
+        self,
+        collection: &str,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Record, SienaError> {
+        let mut existing: Vec<String> = self
+            .find(&ids_query(collection))
+            .into_iter()
+            .map(|record| record.id)
+            .collect();
+
+        loop {
+            let record = self.generated_record(collection, &data, existing.clone())?;
+
+            match self
+                .provider
+                .set_new(Vec::from([record.clone()]), data.clone())
+            {
+                // Taken since the ids were read, so on to the next one
+                Err(SienaError::Exists(_)) => existing.push(record.id),
+                result => return Ok(result?.pop().unwrap_or(record)),
+            }
+        }
+    }
+
+    // Set the Markdown body of all records queried, and persist them. The
+    // body is kept apart from the other data, so it never ends up in the
+    // front matter.
+    pub fn set_body(self, body: &str) -> Result<(), SienaError> {
+        self.set(Vec::from([(
+            frontmatter::CONTENT_RAW,
+            &RecordData::Str(body.to_string()),
+        )]))
+    }
+
+    // Give the first record queried a new `id`, returning the renamed
+    // record, or `None` if there was no record to rename.
+    pub fn rename(self, id: &str) -> Result<Option<Record>, SienaError> {
+        match self.fetch(None)?.into_iter().next() {
+            Some(record) => Ok(Some(self.provider.rename(record, id)?)),
+            None => Ok(None),
+        }
     }
 
     // Delete all records queried from disk.
-    pub fn delete(self) {
-        self.provider.delete(self.records.clone());
+    pub fn delete(self) -> Result<(), SienaError> {
+        self.provider.delete(self.fetch(None)?)
     }
 
-    // Create a new record in a `collection` with the given `id`.
-    //
-    // Note: this alone does not persist the newly created record, and in
-    // order to do so you must also set some data via the `set` method.
-    //
-    // Also note: if the record with such an `id` in given `collection` already
-    // exists, it will be overwritten.
-    pub fn create(mut self, collection: &str, id: &str) -> Siena {
-        self.records.push(Record {
-            id: id.to_string(),
-            collection: collection.to_string(),
-            file_name: format!("{}.yml", id),
-            data: HashMap::new(),
-        });
+    // Delete all records queried from disk, handling the records that
+    // reference them according to `mode`. With `DeleteMode::Restrict`
+    // nothing is deleted when there are referencing records, and they are
+    // returned in `SienaError::Referenced` instead.
+    pub fn delete_with(self, mode: DeleteMode) -> Result<(), SienaError> {
+        let mut records = self.fetch(None)?;
+        let referencing = self.referencing_records(&records);
+
+        match mode {
+            DeleteMode::Restrict => {
+                if !referencing.is_empty() {
+                    let blocking = referencing
+                        .into_iter()
+                        .map(|(record, reference)| BlockingRecord {
+                            collection: record.collection,
+                            id: record.id,
+                            key: reference.key,
+                        })
+                        .collect();
+
+                    return Err(SienaError::Referenced(blocking));
+                }
+            }
+            DeleteMode::Cascade => {
+                let mut referencing = referencing;
+
+                while !referencing.is_empty() {
+                    for (record, _) in referencing {
+                        if !records
+                            .iter()
+                            .any(|r| r.collection == record.collection && r.id == record.id)
+                        {
+                            records.push(record);
+                        }
+                    }
+
+                    referencing = self.referencing_records(&records);
+                }
+            }
+            DeleteMode::Nullify => {
+                // A record can reference the deleted ones through several
+                // keys, which all have to be nullified in the same write.
+                let mut nullified: Vec<(Record, Vec<(String, RecordData)>)> = Vec::new();
+
+                for (record, reference) in referencing {
+                    let ids: Vec<&str> = records
+                        .iter()
+                        .filter(|r| r.collection == reference.target)
+                        .map(|r| r.id.as_str())
+                        .collect();
+
+                    let value = match record.data.get(&reference.key) {
+                        Some(RecordData::Vec(items)) => RecordData::Vec(
+                            items
+                                .iter()
+                                .filter(|item| !references_any(item, &ids))
+                                .cloned()
+                                .collect(),
+                        ),
+                        _ => RecordData::Null,
+                    };
 
-        self
+                    match nullified
+                        .iter_mut()
+                        .find(|(r, _)| r.collection == record.collection && r.id == record.id)
+                    {
+                        Some((_, values)) => values.push((reference.key, value)),
+                        None => nullified.push((record, Vec::from([(reference.key, value)]))),
+                    }
+                }
+
+                for (record, values) in nullified {
+                    let data = values
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value))
+                        .collect();
+
+                    self.provider.set(vec![record], data)?;
+                }
+            }
+        }
+
+        self.provider.delete(records)
+    }
+
+    // Run `f` with a store that stages the `set`, `create`, `rename` and
+    // `delete` calls made through it instead of persisting them, and only
+    // commit them all together once `f` succeeds. Queries made through
+    // the store see the staged changes. When `f` fails, nothing is written.
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(Siena) -> Result<T, SienaError>,
+    ) -> Result<T, SienaError> {
+        let transaction = Arc::new(Transaction::new(self.provider.clone()));
+        let store = Siena {
+            references: self.references.clone(),
+            formats: self.formats.clone(),
+            ids: self.ids.clone(),
+            ..Siena::new(transaction.clone() as Arc<dyn StoreProvider>)
+        };
+        let value = f(store)?;
+
+        self.provider.commit(transaction.changes())?;
+
+        Ok(value)
+    }
+
+    // Find records that reference any of the given `records` through one of
+    // the declared references, excluding the given records themselves.
+    fn referencing_records(&self, records: &[Record]) -> Vec<(Record, RecordReference)> {
+        let mut referencing: Vec<(Record, RecordReference)> = Vec::new();
+        let mut retrieved: HashMap<String, Vec<Record>> = HashMap::new();
+
+        for reference in &self.references {
+            let ids: Vec<&str> = records
+                .iter()
+                .filter(|r| r.collection == reference.target)
+                .map(|r| r.id.as_str())
+                .collect();
+
+            if ids.is_empty() {
+                continue;
+            }
+
+            let candidates = retrieved
+                .entry(reference.collection.clone())
+                .or_insert_with(|| self.provider.retrieve(&reference.collection));
+
+            for candidate in candidates.iter() {
+                let is_deleted = records
+                    .iter()
+                    .any(|r| r.collection == candidate.collection && r.id == candidate.id);
+
+                if is_deleted {
+                    continue;
+                }
+
+                if let Some(value) = candidate.data.get(&reference.key) {
+                    if references_any(value, &ids) {
+                        referencing.push((candidate.clone(), reference.clone()));
+                    }
+                }
+            }
+        }
+
+        referencing
     }
 }
 
-pub fn siena(provider: impl StoreProvider + 'static) -> Siena {
-    Siena {
-        records: vec![],
-        provider: Box::new(provider),
+// A new, empty record in `collection` with the given `id`, stored in
+// `format`.
+fn new_record(collection: &str, id: &str, format: RecordFormat) -> Record {
+    let path = format!("{}.{}", id, format.extension());
+    let data = match format {
+        RecordFormat::Markdown => RecordMap::from([
+            (
+                frontmatter::CONTENT.to_string(),
+                RecordData::Str(String::new()),
+            ),
+            (
+                frontmatter::CONTENT_RAW.to_string(),
+                RecordData::Str(String::new()),
+            ),
+        ]),
+        _ => RecordMap::new(),
+    };
+
+    Record {
+        id: id.to_string(),
+        collection: collection.to_string(),
+        file_name: path.rsplit('/').next().unwrap_or(&path).to_string(),
+        parent: id.rsplit_once('/').map(|(parent, _)| parent.to_string()),
+        path,
+        assets: Vec::new(),
+        version: None,
+        data,
     }
 }
+
+// The query finding the ids of all records in `collection`.
+pub(crate) fn ids_query(collection: &str) -> Query {
+    Query {
+        fields: Some(Vec::new()),
+        ..Query::new(collection)
+    }
+}
+
+// Whether `value` is one of `ids`, or a list containing one of `ids`.
+fn references_any(value: &RecordData, ids: &[&str]) -> bool {
+    match value {
+        RecordData::Str(id) => ids.contains(&id.as_str()),
+        RecordData::Vec(items) => items.iter().any(|item| references_any(item, ids)),
+        _ => false,
+    }
+}
+
+pub fn siena(provider: impl StoreProvider + 'static) -> Siena {
+    Siena::new(Arc::new(provider))
+}
@@ -1,13 +1,39 @@
-use crate::providers::local::LocalProvider;
-use crate::siena::{siena, Record, RecordData, RecordSortOrder};
-use std::{collections::HashMap, env};
+use crate::id::IdStrategy;
+use crate::pagination::Page;
+use crate::providers::local::{self, LocalProvider, LockWait};
+use crate::providers::memory::MemoryProvider;
+use crate::query::Query;
+use crate::siena::{
+    siena, BlockingRecord, DeleteMode, Record, RecordData, RecordFormat, RecordMap,
+    RecordSortOrder, SienaError, StoreProvider,
+};
+use crate::transaction::Change;
+use std::{
+    env, fs,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+// The version of the file at `path` in the test data.
+fn test_data_version(path: &str) -> Option<String> {
+    let root_dir = env::current_dir().unwrap();
+
+    fs::read_to_string(root_dir.join("test_data").join(path))
+        .ok()
+        .map(|contents| local::version(&contents))
+}
 
 fn record_1() -> Record {
     Record {
         id: String::from("test"),
         collection: String::from("demo"),
         file_name: String::from("test.yaml"),
-        data: HashMap::from([
+        path: String::from("test.yaml"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/test.yaml"),
+        data: RecordMap::from([
             (
                 String::from("title"),
                 RecordData::Str(String::from("Bye, world")),
@@ -25,7 +51,11 @@ fn record_2() -> Record {
         id: String::from("test2"),
         collection: String::from("demo"),
         file_name: String::from("test2.yml"),
-        data: HashMap::from([
+        path: String::from("test2.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/test2.yml"),
+        data: RecordMap::from([
             (
                 String::from("title"),
                 RecordData::Str(String::from("Hello, world")),
@@ -43,7 +73,11 @@ fn record_3() -> Record {
         id: String::from("2"),
         collection: String::from("demo"),
         file_name: String::from("2.yml"),
-        data: HashMap::from([
+        path: String::from("2.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/2.yml"),
+        data: RecordMap::from([
             (
                 String::from("title"),
                 RecordData::Str(String::from("Bye, World")),
@@ -61,7 +95,11 @@ fn record_4() -> Record {
         id: String::from("1"),
         collection: String::from("demo"),
         file_name: String::from("1.yml"),
-        data: HashMap::from([
+        path: String::from("1.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/1.yml"),
+        data: RecordMap::from([
             (
                 String::from("title"),
                 RecordData::Str(String::from("Hello, World")),
@@ -79,7 +117,11 @@ fn record_5() -> Record {
         id: String::from("3"),
         collection: String::from("demo"),
         file_name: String::from("3.yml"),
-        data: HashMap::from([
+        path: String::from("3.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/3.yml"),
+        data: RecordMap::from([
             (
                 String::from("special-item"),
                 RecordData::Str(String::from("true")),
@@ -97,7 +139,11 @@ fn record_6() -> Record {
         id: String::from("markdown"),
         collection: String::from("demo"),
         file_name: String::from("markdown.md"),
-        data: HashMap::from([
+        path: String::from("markdown.md"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/markdown.md"),
+        data: RecordMap::from([
             (
                 String::from("title"),
                 RecordData::Str(String::from("Hello, Markdown")),
@@ -157,9 +203,7 @@ fn when_is_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -178,9 +222,7 @@ fn when_is_md_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store.collection("demo").when_is("id", "markdown").get_all();
@@ -196,9 +238,7 @@ fn when_is_not_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -223,9 +263,7 @@ fn when_has_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store.collection("demo").when_has("special-item").get_all();
@@ -241,9 +279,7 @@ fn when_has_not_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let expected = Vec::from([record_6(), record_1(), record_2(), record_3(), record_4()]);
@@ -265,9 +301,7 @@ fn when_matches_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -286,9 +320,7 @@ fn limit_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -308,9 +340,7 @@ fn offset_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -331,9 +361,7 @@ fn offset_out_of_bounds_test() {
         root_dir.display().to_string().as_str(),
         "/test_data"
     );
-    let provider = LocalProvider {
-        directory: local_dir,
-    };
+    let provider = LocalProvider::new(&local_dir);
     let store = siena(provider);
 
     let result = store
@@ -347,14 +375,15 @@ fn offset_out_of_bounds_test() {
 
 #[test]
 fn update_test() {
-    let root_dir = env::current_dir().unwrap();
-    let local_dir = format!(
-        "{}{}",
-        root_dir.display().to_string().as_str(),
-        "/test_data"
+    let local_dir = temp_store(
+        "update",
+        &[("demo/3.yml", "special-item: 'true'\ndate: 1992-09-17\n")],
     );
-    let provider = LocalProvider {
-        directory: local_dir,
+    let provider = LocalProvider::new(&local_dir);
+    let version = |path: &str| {
+        fs::read_to_string(format!("{}/{}", local_dir, path))
+            .ok()
+            .map(|contents| local::version(&contents))
     };
 
     siena(provider.clone())
@@ -363,7 +392,8 @@ fn update_test() {
         .set(Vec::from([(
             "special-item",
             &RecordData::Str("false".to_string()),
-        )]));
+        )]))
+        .unwrap();
 
     let result = siena(provider.clone())
         .collection("demo")
@@ -375,7 +405,11 @@ fn update_test() {
         id: String::from("3"),
         collection: String::from("demo"),
         file_name: String::from("3.yml"),
-        data: HashMap::from([
+        path: String::from("3.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: version("demo/3.yml"),
+        data: RecordMap::from([
             (
                 String::from("special-item"),
                 RecordData::Str(String::from("false")),
@@ -395,7 +429,8 @@ fn update_test() {
         .set(Vec::from([(
             "special-item",
             &RecordData::Str("true".to_string()),
-        )]));
+        )]))
+        .unwrap();
 
     let result_again = siena(provider.clone())
         .collection("demo")
@@ -407,7 +442,11 @@ fn update_test() {
         id: String::from("3"),
         collection: String::from("demo"),
         file_name: String::from("3.yml"),
-        data: HashMap::from([
+        path: String::from("3.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: version("demo/3.yml"),
+        data: RecordMap::from([
             (
                 String::from("special-item"),
                 RecordData::Str(String::from("true")),
@@ -424,14 +463,12 @@ fn update_test() {
 
 #[test]
 fn create_test() {
-    let root_dir = env::current_dir().unwrap();
-    let local_dir = format!(
-        "{}{}",
-        root_dir.display().to_string().as_str(),
-        "/test_data"
-    );
-    let provider = LocalProvider {
-        directory: local_dir,
+    let local_dir = temp_store("create", &[]);
+    let provider = LocalProvider::new(&local_dir);
+    let version = |path: &str| {
+        fs::read_to_string(format!("{}/{}", local_dir, path))
+            .ok()
+            .map(|contents| local::version(&contents))
     };
 
     siena(provider.clone())
@@ -439,7 +476,8 @@ fn create_test() {
         .set(Vec::from([(
             "title",
             &RecordData::Str("Title goes here".to_string()),
-        )]));
+        )]))
+        .unwrap();
 
     let result = siena(provider.clone())
         .collection("demo2")
@@ -450,7 +488,11 @@ fn create_test() {
         id: String::from("test3"),
         collection: String::from("demo2"),
         file_name: String::from("test3.yml"),
-        data: HashMap::from([(
+        path: String::from("test3.yml"),
+        parent: None,
+        assets: Vec::new(),
+        version: version("demo2/test3.yml"),
+        data: RecordMap::from([(
             String::from("title"),
             RecordData::Str(String::from("Title goes here")),
         )]),
@@ -459,7 +501,1896 @@ fn create_test() {
     siena(provider.clone())
         .collection("demo2")
         .when_is("id", "test3")
-        .delete();
+        .delete()
+        .unwrap();
+
+    assert_eq!(result, expected);
+}
+
+// Creates a fresh store directory in the system temp dir, with `files`
+// given as (path, contents) pairs relative to the store root. Shared by
+// the tests of all providers.
+pub(crate) fn temp_store(name: &str, files: &[(&str, &str)]) -> String {
+    let dir = env::temp_dir().join(format!("siena-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    fs::create_dir_all(&dir).unwrap();
+
+    for (path, contents) in files {
+        let file = dir.join(path);
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(file, contents).unwrap();
+    }
+
+    dir.display().to_string()
+}
+
+fn references_provider() -> MemoryProvider {
+    let text = |s: &str| RecordData::Str(s.to_string());
+
+    MemoryProvider::new()
+        .with_record("authors", "jane", Vec::from([("name", text("Jane"))]))
+        .with_record("authors", "john", Vec::from([("name", text("John"))]))
+        .with_record(
+            "posts",
+            "hello",
+            Vec::from([("title", text("Hello")), ("author", text("jane"))]),
+        )
+        .with_record(
+            "posts",
+            "bye",
+            Vec::from([("title", text("Bye")), ("author", text("john"))]),
+        )
+        .with_record(
+            "comments",
+            "first",
+            Vec::from([
+                ("post", text("hello")),
+                (
+                    "reviewers",
+                    RecordData::Vec(Vec::from([text("jane"), text("john")])),
+                ),
+            ]),
+        )
+}
+
+#[test]
+fn delete_restrict_test() {
+    let provider = references_provider();
+
+    let result = siena(provider.clone())
+        .reference("posts", "author", "authors")
+        .reference("comments", "reviewers", "authors")
+        .collection("authors")
+        .when_is("id", "jane")
+        .delete_with(DeleteMode::Restrict);
+
+    let mut blocking = match result {
+        Err(SienaError::Referenced(blocking)) => blocking,
+        _ => panic!("Expected the delete to be refused"),
+    };
+
+    blocking.sort_by(|a, b| a.collection.cmp(&b.collection));
+
+    assert_eq!(
+        blocking,
+        Vec::from([
+            BlockingRecord {
+                collection: String::from("comments"),
+                id: String::from("first"),
+                key: String::from("reviewers"),
+            },
+            BlockingRecord {
+                collection: String::from("posts"),
+                id: String::from("hello"),
+                key: String::from("author"),
+            },
+        ])
+    );
+
+    let authors = siena(provider.clone()).collection("authors").get_all();
+
+    assert_eq!(authors.len(), 2);
+}
+
+#[test]
+fn delete_cascade_test() {
+    let provider = references_provider();
+
+    siena(provider.clone())
+        .reference("posts", "author", "authors")
+        .reference("comments", "post", "posts")
+        .collection("authors")
+        .when_is("id", "jane")
+        .delete_with(DeleteMode::Cascade)
+        .unwrap();
+
+    let authors = siena(provider.clone())
+        .collection("authors")
+        .sort("id", RecordSortOrder::Asc)
+        .get_all();
+    let posts = siena(provider.clone()).collection("posts").get_all();
+    let comments = siena(provider.clone()).collection("comments").get_all();
+
+    assert_eq!(authors.len(), 1);
+    assert_eq!(authors[0].id, "john");
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].id, "bye");
+    assert!(comments.is_empty());
+}
+
+#[test]
+fn delete_nullify_test() {
+    let provider = references_provider();
+
+    siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .set(Vec::from([(
+            "editor",
+            &RecordData::Str(String::from("jane")),
+        )]))
+        .unwrap();
+
+    siena(provider.clone())
+        .reference("posts", "author", "authors")
+        .reference("posts", "editor", "authors")
+        .reference("comments", "reviewers", "authors")
+        .collection("authors")
+        .when_is("id", "jane")
+        .delete_with(DeleteMode::Nullify)
+        .unwrap();
+
+    let post = siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "hello")
+        .get_first()
+        .unwrap();
+    let comment = siena(provider.clone())
+        .collection("comments")
+        .get_first()
+        .unwrap();
+
+    assert_eq!(post.data.get("author"), Some(&RecordData::Null));
+    assert_eq!(post.data.get("editor"), Some(&RecordData::Null));
+    assert_eq!(
+        comment.data.get("reviewers"),
+        Some(&RecordData::Vec(Vec::from([RecordData::Str(
+            String::from("john")
+        )])))
+    );
+    assert!(siena(provider.clone())
+        .collection("authors")
+        .when_is("id", "jane")
+        .get_first()
+        .is_none());
+}
+
+#[test]
+fn select_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let provider = LocalProvider::new(&local_dir);
+    let store = siena(provider);
+
+    let result = store
+        .collection("demo")
+        .when_is("status", "published")
+        .select(Vec::from(["title", "content"]))
+        .get_first()
+        .unwrap();
+
+    let expected = Record {
+        id: String::from("markdown"),
+        collection: String::from("demo"),
+        file_name: String::from("markdown.md"),
+        path: String::from("markdown.md"),
+        parent: None,
+        assets: Vec::new(),
+        version: test_data_version("demo/markdown.md"),
+        data: RecordMap::from([
+            (
+                String::from("title"),
+                RecordData::Str(String::from("Hello, Markdown")),
+            ),
+            (
+                String::from("content"),
+                RecordData::Str(String::from("<p>:)</p>\n<hr />\n<p>:)</p>\n")),
+            ),
+        ]),
+    };
 
     assert_eq!(result, expected);
 }
+
+#[test]
+fn query_body_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let store = siena(LocalProvider::new(&local_dir));
+
+    let matching = store
+        .clone()
+        .collection("demo")
+        .when_matches("content_raw", ":\\)")
+        .select(Vec::from(["title"]))
+        .get_all();
+    let having = store
+        .collection("demo")
+        .when_has("content")
+        .select(Vec::from(["title"]))
+        .get_all();
+
+    assert_eq!(
+        matching
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect::<Vec<&str>>(),
+        Vec::from(["markdown"])
+    );
+    assert_eq!(
+        having.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(),
+        Vec::from(["markdown"])
+    );
+}
+
+// Records the queries it is offered, and leaves their evaluation to Siena.
+#[derive(Clone, Default)]
+struct QueryLogProvider {
+    queries: Arc<Mutex<Vec<Query>>>,
+}
+
+impl StoreProvider for QueryLogProvider {
+    fn retrieve(&self, _name: &str) -> Vec<Record> {
+        Vec::from([record_1(), record_2(), record_3(), record_4()])
+    }
+
+    fn query(&self, query: &Query) -> Option<Vec<Record>> {
+        self.queries.lock().unwrap().push(query.clone());
+
+        None
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        _data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        Ok(records)
+    }
+
+    fn delete(&self, _records: Vec<Record>) -> Result<(), SienaError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn query_plan_test() {
+    let provider = QueryLogProvider::default();
+
+    let result = siena(provider.clone())
+        .collection("demo")
+        .when_matches("title", "(?i)world")
+        .sort("date", RecordSortOrder::Desc)
+        .when_is_not("id", "test")
+        .offset(1)
+        .limit(5)
+        .offset(1)
+        .select(Vec::from(["title"]))
+        .get_all();
+
+    let query = provider.queries.lock().unwrap().pop().unwrap();
+
+    assert_eq!(query.collection, "demo");
+    assert_eq!(query.filters.len(), 2);
+    assert_eq!(query.sort.len(), 1);
+    assert_eq!(query.offset, 2);
+    assert_eq!(query.limit, Some(4));
+    assert_eq!(query.fields, Some(Vec::from([String::from("title")])));
+    assert_eq!(
+        result.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(),
+        Vec::from(["1"])
+    );
+    assert_eq!(result[0].data.len(), 1);
+
+    // Filtering after limiting can't be described by a single query, so
+    // it's applied on the result instead.
+    let result = siena(provider.clone())
+        .collection("demo")
+        .sort("id", RecordSortOrder::Asc)
+        .limit(2)
+        .when_is("title", "Bye, World")
+        .select(Vec::from(["title"]))
+        .get_all();
+
+    let query = provider.queries.lock().unwrap().pop().unwrap();
+
+    assert_eq!(query.filters.len(), 0);
+    assert_eq!(query.limit, Some(2));
+    assert_eq!(query.fields, None);
+    assert_eq!(
+        result.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(),
+        Vec::from(["2"])
+    );
+}
+
+#[test]
+fn iter_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let provider = LocalProvider::new(&local_dir);
+    let store = siena(provider);
+
+    let result: Vec<Record> = store
+        .collection("demo")
+        .when_has_not("special-item")
+        .sort("date", RecordSortOrder::Desc)
+        .offset(1)
+        .limit(2)
+        .iter()
+        .collect();
+
+    assert_eq!(result, Vec::from([record_1(), record_2()]));
+}
+
+// Streams a never-ending collection, which can only be queried lazily.
+struct EndlessProvider;
+
+impl StoreProvider for EndlessProvider {
+    fn retrieve(&self, _name: &str) -> Vec<Record> {
+        panic!("An endless collection can't be retrieved at once")
+    }
+
+    fn stream(&self, name: &str) -> Box<dyn Iterator<Item = Record> + Send> {
+        let name = name.to_string();
+
+        Box::new((0..).map(move |i: usize| Record {
+            id: i.to_string(),
+            collection: name.clone(),
+            file_name: format!("{}.yml", i),
+            data: RecordMap::from([(String::from("n"), RecordData::Num(i))]),
+            ..Default::default()
+        }))
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        _data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        Ok(records)
+    }
+
+    fn delete(&self, _records: Vec<Record>) -> Result<(), SienaError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn iter_lazy_test() {
+    let result: Vec<String> = siena(EndlessProvider)
+        .collection("numbers")
+        .when_matches("id", "7$")
+        .offset(1)
+        .limit(3)
+        .iter()
+        .map(|r| r.id)
+        .collect();
+
+    assert_eq!(result, Vec::from(["17", "27", "37"]));
+}
+
+#[test]
+fn concurrent_test() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let provider = LocalProvider::new(&temp_store(
+        "concurrent",
+        &[("posts/a.yml", "title: a"), ("posts/b.yml", "title: b")],
+    ));
+    let store = siena(provider);
+
+    assert_send_sync(&store);
+
+    let writers: Vec<thread::JoinHandle<()>> = (0..4)
+        .map(|i| {
+            let store = store.clone();
+
+            thread::spawn(move || {
+                for j in 0..25 {
+                    // Alternate between long and short values, so that partial
+                    // writes would show up as unparseable files.
+                    let title = match (i + j) % 2 {
+                        0 => "x".repeat(200),
+                        _ => String::from("y"),
+                    };
+
+                    store
+                        .clone()
+                        .collection("posts")
+                        .set(Vec::from([("title", &RecordData::Str(title))]))
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+
+    let readers: Vec<thread::JoinHandle<()>> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+
+            thread::spawn(move || {
+                for _ in 0..25 {
+                    let records = store.clone().collection("posts").get_all();
+
+                    assert_eq!(records.len(), 2);
+                    assert!(records.iter().all(|r| r.data.contains_key("title")));
+                }
+            })
+        })
+        .collect();
+
+    for handle in writers.into_iter().chain(readers) {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn cursor_page_test() {
+    let date = |s: &str| Vec::from([("date", RecordData::Str(s.to_string()))]);
+    let provider = MemoryProvider::new()
+        .with_record("posts", "a", date("2023-01-01"))
+        .with_record("posts", "b", date("2023-01-02"))
+        .with_record("posts", "c", date("2023-01-02"))
+        .with_record("posts", "d", date("2023-01-03"))
+        .with_record("posts", "e", date("2023-01-05"));
+
+    let ids =
+        |records: &Vec<Record>| -> Vec<String> { records.iter().map(|r| r.id.clone()).collect() };
+    let query = || {
+        siena(provider.clone())
+            .collection("posts")
+            .sort("date", RecordSortOrder::Desc)
+    };
+
+    let first = query().get_page(2).unwrap();
+
+    assert_eq!(ids(&first.records), Vec::from(["e", "d"]));
+    assert_eq!(first.total, 5);
+    assert!(first.has_next && !first.has_prev);
+    assert_eq!(first.prev_cursor, None);
+
+    // A record added in front of the first page doesn't shift the next one.
+    siena(provider.clone())
+        .create("posts", "f")
+        .set(Vec::from([(
+            "date",
+            &RecordData::Str(String::from("2023-01-06")),
+        )]))
+        .unwrap();
+
+    let second = query()
+        .after(first.next_cursor.as_ref().unwrap())
+        .get_page(2)
+        .unwrap();
+
+    assert_eq!(ids(&second.records), Vec::from(["b", "c"]));
+    assert_eq!(second.total, 6);
+    assert!(second.has_next && second.has_prev);
+
+    let third = query()
+        .after(second.next_cursor.as_ref().unwrap())
+        .get_page(2)
+        .unwrap();
+
+    assert_eq!(ids(&third.records), Vec::from(["a"]));
+    assert!(!third.has_next && third.has_prev);
+    assert_eq!(third.next_cursor, None);
+
+    let back = query()
+        .before(third.prev_cursor.as_ref().unwrap())
+        .get_page(2)
+        .unwrap();
+
+    assert_eq!(back, second);
+    assert!(matches!(
+        query().after("nope").get_page(2),
+        Err(SienaError::InvalidCursor(_))
+    ));
+
+    // The other methods keep to the cursor too
+    let cursor = first.next_cursor.unwrap();
+
+    assert_eq!(
+        ids(&query().after(&cursor).get_all()),
+        Vec::from(["b", "c", "a"])
+    );
+    assert_eq!(
+        ids(&query().before(&cursor).get_all()),
+        Vec::from(["f", "e"])
+    );
+    assert_eq!(query().after(&cursor).get_first().unwrap().id, "b");
+    assert_eq!(query().after(&cursor).get_last().unwrap().id, "a");
+    assert_eq!(
+        query()
+            .after(&cursor)
+            .iter()
+            .map(|r| r.id)
+            .collect::<Vec<String>>(),
+        Vec::from(["b", "c", "a"])
+    );
+    assert_eq!(
+        ids(&query().after(&cursor).paginate_with_meta(2, 2).records),
+        Vec::from(["a"])
+    );
+    assert!(query().after("nope").get_all().is_empty());
+    assert!(matches!(
+        query().after("nope").delete(),
+        Err(SienaError::InvalidCursor(_))
+    ));
+
+    query().before(&cursor).delete().unwrap();
+
+    assert_eq!(ids(&query().get_all()), Vec::from(["d", "b", "c", "a"]));
+}
+
+#[test]
+fn paginate_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let provider = LocalProvider::new(&local_dir);
+
+    let page_0 = siena(provider.clone())
+        .collection("demo")
+        .sort("date", RecordSortOrder::Desc)
+        .paginate(0, 2)
+        .get_all();
+    let page_2 = siena(provider.clone())
+        .collection("demo")
+        .sort("date", RecordSortOrder::Desc)
+        .paginate(2, 2)
+        .get_all();
+
+    assert_eq!(page_0, Vec::from([record_6(), record_1()]));
+    assert_eq!(page_2, Vec::from([record_2(), record_3()]));
+    assert!(siena(provider)
+        .collection("demo")
+        .paginate(usize::MAX, 2)
+        .get_all()
+        .is_empty());
+}
+
+#[test]
+fn paginate_with_meta_test() {
+    let root_dir = env::current_dir().unwrap();
+    let local_dir = format!(
+        "{}{}",
+        root_dir.display().to_string().as_str(),
+        "/test_data"
+    );
+    let provider = LocalProvider::new(&local_dir);
+
+    let page = siena(provider.clone())
+        .collection("demo")
+        .sort("date", RecordSortOrder::Desc)
+        .paginate_with_meta(2, 4);
+
+    assert_eq!(
+        page,
+        Page {
+            records: Vec::from([record_4(), record_5()]),
+            total: 6,
+            total_pages: 2,
+            page: 2,
+            per_page: 4,
+            prev_page: Some(1),
+            next_page: None,
+        }
+    );
+
+    let page = siena(provider.clone())
+        .collection("demo")
+        .when_has("special-item")
+        .paginate_with_meta(1, 4);
+
+    assert_eq!(page.total_pages, 1);
+    assert_eq!(page.prev_page, None);
+    assert_eq!(page.next_page, None);
+
+    let page = siena(provider)
+        .collection("demo")
+        .paginate_with_meta(usize::MAX, 2);
+
+    assert!(page.records.is_empty());
+    assert_eq!(page.next_page, None);
+}
+
+#[test]
+fn json_toml_test() {
+    let directory = temp_store(
+        "json-toml",
+        &[
+            (
+                "posts/a.json",
+                r#"{"title": "A", "order": 2, "tags": ["x"]}"#,
+            ),
+            (
+                "posts/b.toml",
+                "title = \"B\"\norder = 1\n\n[author]\nname = \"Jane\"\n",
+            ),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    let records = store
+        .clone()
+        .collection("posts")
+        .sort("order", RecordSortOrder::Asc)
+        .get_all();
+
+    assert_eq!(
+        records.iter().map(|r| r.id.as_str()).collect::<Vec<&str>>(),
+        Vec::from(["b", "a"])
+    );
+    assert_eq!(
+        records[0].data.get("author"),
+        Some(&RecordData::Map(RecordMap::from([(
+            String::from("name"),
+            RecordData::Str(String::from("Jane"))
+        )])))
+    );
+
+    store
+        .clone()
+        .collection("posts")
+        .set(Vec::from([
+            ("status", &RecordData::Str(String::from("draft"))),
+            ("draft", &RecordData::Null),
+        ]))
+        .unwrap();
+
+    let json = fs::read_to_string(format!("{}/posts/a.json", directory)).unwrap();
+    let toml = fs::read_to_string(format!("{}/posts/b.toml", directory)).unwrap();
+
+    assert!(json.contains("\"status\": \"draft\""));
+    assert!(toml.contains("status = \"draft\""));
+    assert!(!toml.contains("draft ="));
+
+    let records = store
+        .collection("posts")
+        .when_is("status", "draft")
+        .get_all();
+
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|r| r.data.contains_key("order")));
+}
+
+#[test]
+fn front_matter_style_test() {
+    let directory = temp_store(
+        "front-matter-style",
+        &[
+            ("posts/a.md", "+++\ntitle = \"A\"\n+++\n\nHello from A."),
+            ("posts/b.md", "{\n  \"title\": \"B\"\n}\n\nHello from B."),
+            ("posts/c.md", "---\ntitle: C\n---\n\nHello from C."),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    store
+        .clone()
+        .collection("posts")
+        .set(Vec::from([(
+            "status",
+            &RecordData::Str(String::from("draft")),
+        )]))
+        .unwrap();
+
+    for (id, delimiter) in [("a", "+++\n"), ("b", "{\n"), ("c", "---\n")] {
+        let contents = fs::read_to_string(format!("{}/posts/{}.md", directory, id)).unwrap();
+
+        assert!(contents.starts_with(delimiter), "{}", contents);
+    }
+
+    let records = store
+        .collection("posts")
+        .when_is("status", "draft")
+        .sort("id", RecordSortOrder::Asc)
+        .get_all();
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(
+        records[1].data.get("content_raw"),
+        Some(&RecordData::Str(String::from("Hello from B.")))
+    );
+}
+
+#[test]
+fn front_matter_crlf_test() {
+    let directory = temp_store(
+        "front-matter-crlf",
+        &[(
+            "posts/a.md",
+            "---\r\ntitle: A\r\n---\r\n\r\nHello from A.\r\n",
+        )],
+    );
+    let store = siena(LocalProvider::new(&directory));
+    let post = store.clone().collection("posts").get_first().unwrap();
+
+    assert_eq!(
+        post.data.get("title"),
+        Some(&RecordData::Str(String::from("A")))
+    );
+    assert_eq!(
+        post.data.get("content_raw"),
+        Some(&RecordData::Str(String::from("Hello from A.")))
+    );
+
+    store
+        .collection("posts")
+        .set(Vec::from([(
+            "status",
+            &RecordData::Str(String::from("draft")),
+        )]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.md", directory)).unwrap(),
+        "---\r\ntitle: A\r\nstatus: draft\r\n---\r\n\r\nHello from A."
+    );
+}
+
+#[test]
+fn front_matter_unreadable_test() {
+    let contents = "---\ntitle: Old\nrating: 4.5\n---\n\nBody";
+    let directory = temp_store("front-matter-unreadable", &[("posts/a.md", contents)]);
+    let result = siena(LocalProvider::new(&directory))
+        .collection("posts")
+        .set(Vec::from([(
+            "status",
+            &RecordData::Str(String::from("draft")),
+        )]));
+
+    assert!(matches!(result, Err(SienaError::Io(_))));
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.md", directory)).unwrap(),
+        contents
+    );
+}
+
+#[test]
+fn toml_datetime_test() {
+    let directory = temp_store(
+        "toml-datetime",
+        &[
+            (
+                "posts/a.md",
+                "+++\ntitle = \"A\"\ndate = 2024-01-05T10:00:00Z\n+++\n\nHello from A.",
+            ),
+            ("settings/site.toml", "updated = 2024-01-05\n"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    for collection in ["posts", "settings"] {
+        store
+            .clone()
+            .collection(collection)
+            .set(Vec::from([("draft", &RecordData::Bool(true))]))
+            .unwrap();
+    }
+
+    let post = store.clone().collection("posts").get_first().unwrap();
+    let site = store.collection("settings").get_first().unwrap();
+
+    assert_eq!(
+        post.data.get("date"),
+        Some(&RecordData::Str(String::from("2024-01-05T10:00:00Z")))
+    );
+    assert_eq!(
+        site.data.get("updated"),
+        Some(&RecordData::Str(String::from("2024-01-05")))
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.md", directory)).unwrap(),
+        "+++\ntitle = \"A\"\ndate = 2024-01-05T10:00:00Z\ndraft = true\n+++\n\nHello from A."
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/settings/site.toml", directory)).unwrap(),
+        "updated = 2024-01-05\ndraft = true\n"
+    );
+}
+
+#[test]
+fn collection_file_test() {
+    let directory = temp_store(
+        "collection-file",
+        &[
+            (
+                "navigation.yml",
+                "- id: home\n  title: Home\n- id: about\n  title: About\n",
+            ),
+            (
+                "redirects.jsonl",
+                "{\"id\": \"old\", \"to\": \"/new\"}\n{\"id\": 2, \"to\": \"/two\"}\n",
+            ),
+            ("menu.json", r#"[{"id": "main", "items": 3}]"#),
+            (
+                "authors.yml",
+                "- name: Jane\n  id: 1\n- name: John\n  id: 2\n",
+            ),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+    let ids = |name: &str| -> Vec<String> {
+        store
+            .clone()
+            .collection(name)
+            .get_all()
+            .into_iter()
+            .map(|r| r.id)
+            .collect()
+    };
+
+    assert_eq!(ids("navigation"), Vec::from(["home", "about"]));
+    assert_eq!(ids("redirects"), Vec::from(["old", "2"]));
+    assert_eq!(ids("menu"), Vec::from(["main"]));
+
+    store
+        .clone()
+        .collection("navigation")
+        .when_is("id", "about")
+        .set(Vec::from([(
+            "title",
+            &RecordData::Str(String::from("About us")),
+        )]))
+        .unwrap();
+    store
+        .clone()
+        .create("navigation", "blog")
+        .set(Vec::from([(
+            "title",
+            &RecordData::Str(String::from("Blog")),
+        )]))
+        .unwrap();
+    store
+        .clone()
+        .collection("redirects")
+        .when_is("id", "old")
+        .delete()
+        .unwrap();
+
+    let navigation = store.clone().collection("navigation").get_all();
+
+    assert_eq!(
+        navigation
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect::<Vec<&str>>(),
+        Vec::from(["home", "about", "blog"])
+    );
+    assert_eq!(
+        navigation[1].data.get("title"),
+        Some(&RecordData::Str(String::from("About us")))
+    );
+    assert_eq!(ids("redirects"), Vec::from(["2"]));
+    assert_eq!(
+        fs::read_to_string(format!("{}/redirects.jsonl", directory)).unwrap(),
+        "{\"id\":2,\"to\":\"/two\"}\n"
+    );
+
+    // Ids are written back as they were, numbers staying numbers
+    store
+        .clone()
+        .collection("authors")
+        .when_is("id", "1")
+        .set(Vec::from([(
+            "name",
+            &RecordData::Str(String::from("Janet")),
+        )]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/authors.yml", directory)).unwrap(),
+        "- name: Janet\n  id: 1\n- name: John\n  id: 2\n"
+    );
+    assert!(!Path::new(&format!("{}/navigation", directory)).exists());
+}
+
+#[test]
+fn nested_collection_test() {
+    let directory = temp_store(
+        "nested-collection",
+        &[
+            ("docs/index.md", "---\ntitle: Docs\n---\n\nWelcome."),
+            (
+                "docs/guides/intro.md",
+                "---\ntitle: Intro\n---\n\nStart here.",
+            ),
+            ("docs/guides/advanced/tips.yml", "title: Tips"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    let docs = store
+        .clone()
+        .collection("docs")
+        .sort("id", RecordSortOrder::Asc)
+        .get_all();
+
+    assert_eq!(
+        docs.iter()
+            .map(|r| (r.id.as_str(), r.path.as_str(), r.parent.as_deref()))
+            .collect::<Vec<_>>(),
+        Vec::from([
+            (
+                "guides/advanced/tips",
+                "guides/advanced/tips.yml",
+                Some("guides/advanced")
+            ),
+            ("guides/intro", "guides/intro.md", Some("guides")),
+            ("index", "index.md", None),
+        ])
+    );
+
+    let guides = store
+        .clone()
+        .collection("docs/guides")
+        .when_is("id", "intro")
+        .get_first()
+        .unwrap();
+
+    assert_eq!(guides.parent, None);
+    assert_eq!(
+        guides.data.get("content_raw"),
+        Some(&RecordData::Str(String::from("Start here.")))
+    );
+
+    store
+        .clone()
+        .collection("docs")
+        .when_is("id", "guides/advanced/tips")
+        .set(Vec::from([("draft", &RecordData::Bool(true))]))
+        .unwrap();
+    store
+        .clone()
+        .create("docs", "guides/setup")
+        .set(Vec::from([(
+            "title",
+            &RecordData::Str(String::from("Setup")),
+        )]))
+        .unwrap();
+
+    assert!(
+        fs::read_to_string(format!("{}/docs/guides/advanced/tips.yml", directory))
+            .unwrap()
+            .contains("draft: true")
+    );
+    assert!(Path::new(&format!("{}/docs/guides/setup.yml", directory)).exists());
+
+    store
+        .collection("docs")
+        .when_is("id", "guides/setup")
+        .delete()
+        .unwrap();
+
+    assert!(!Path::new(&format!("{}/docs/guides/setup.yml", directory)).exists());
+}
+
+#[test]
+fn page_bundle_test() {
+    let directory = temp_store(
+        "page-bundle",
+        &[
+            ("posts/hello/index.md", "---\ntitle: Hello\n---\n\nHi."),
+            ("posts/hello/cover.jpg", "jpg"),
+            ("posts/hello/images/diagram.png", "png"),
+            ("posts/bye.md", "---\ntitle: Bye\n---\n\nBye."),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    let hello = store
+        .clone()
+        .collection("posts")
+        .when_is("title", "Hello")
+        .get_first()
+        .unwrap();
+
+    assert_eq!(hello.id, "hello");
+    assert_eq!(hello.path, "hello/index.md");
+    assert_eq!(hello.parent, None);
+    assert_eq!(hello.assets, Vec::from(["cover.jpg", "images/diagram.png"]));
+    assert_eq!(store.clone().collection("posts").get_all().len(), 2);
+
+    let renamed = store
+        .clone()
+        .collection("posts")
+        .when_is("id", "hello")
+        .rename("hello-world")
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(renamed.path, "hello-world/index.md");
+    assert!(!Path::new(&format!("{}/posts/hello", directory)).exists());
+    assert!(Path::new(&format!("{}/posts/hello-world/cover.jpg", directory)).exists());
+
+    store
+        .clone()
+        .collection("posts")
+        .when_is("id", "bye")
+        .rename("farewell")
+        .unwrap();
+
+    assert!(Path::new(&format!("{}/posts/farewell.md", directory)).exists());
+
+    store
+        .clone()
+        .collection("posts")
+        .when_is("id", "hello-world")
+        .delete()
+        .unwrap();
+
+    assert!(!Path::new(&format!("{}/posts/hello-world", directory)).exists());
+    assert_eq!(
+        store
+            .collection("posts")
+            .get_all()
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<String>>(),
+        Vec::from(["farewell"])
+    );
+}
+
+#[test]
+fn page_bundle_nested_collection_test() {
+    let directory = temp_store(
+        "page-bundle-nested",
+        &[
+            ("docs/guides/index.md", "---\ntitle: Guides\n---\n"),
+            ("docs/guides/intro.md", "---\ntitle: Intro\n---\n"),
+            ("docs/guides/setup.md", "---\ntitle: Setup\n---\n"),
+            ("docs/guides/diagram.png", "png"),
+            ("docs/faq/index.md", "---\ntitle: FAQ\n---\n"),
+        ],
+    );
+    let provider = LocalProvider::new(&directory);
+    let store = siena(provider.clone());
+
+    let docs = store
+        .clone()
+        .collection("docs")
+        .sort("id", RecordSortOrder::Asc)
+        .get_all();
+
+    // A directory with other records next to its index isn't a page bundle
+    assert_eq!(
+        docs.iter()
+            .map(|r| (r.id.as_str(), r.assets.len()))
+            .collect::<Vec<_>>(),
+        Vec::from([
+            ("faq", 0),
+            ("guides/index", 0),
+            ("guides/intro", 0),
+            ("guides/setup", 0)
+        ])
+    );
+
+    store
+        .clone()
+        .collection("docs")
+        .when_is("id", "guides/index")
+        .delete()
+        .unwrap();
+
+    assert!(!Path::new(&format!("{}/docs/guides/index.md", directory)).exists());
+    assert!(Path::new(&format!("{}/docs/guides/intro.md", directory)).exists());
+    assert!(Path::new(&format!("{}/docs/guides/setup.md", directory)).exists());
+
+    // A bundle that got other records since it was read keeps them
+    let faq = docs.into_iter().find(|r| r.id == "faq").unwrap();
+
+    fs::write(
+        format!("{}/docs/faq/billing.md", directory),
+        "---\ntitle: Billing\n---\n",
+    )
+    .unwrap();
+    provider.delete(Vec::from([faq])).unwrap();
+
+    assert!(Path::new(&format!("{}/docs/faq/billing.md", directory)).exists());
+    assert_eq!(store.collection("docs").get_all().len(), 3);
+}
+
+#[test]
+fn rename_existing_test() {
+    let directory = temp_store(
+        "rename-existing",
+        &[
+            ("posts/a.yml", "title: A\n"),
+            ("posts/b.md", "---\ntitle: B\n---\n"),
+            ("posts/c/index.md", "---\ntitle: C\n---\n"),
+            ("navigation.yml", "- id: home\n- id: blog\n"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+    let rename = |collection: &str, from: &str, to: &str| {
+        store
+            .clone()
+            .collection(collection)
+            .when_is("id", from)
+            .rename(to)
+    };
+
+    for (collection, from, to) in [
+        ("posts", "a", "b"),
+        ("posts", "a", "c"),
+        ("navigation", "home", "blog"),
+    ] {
+        let result = rename(collection, from, to);
+
+        assert!(matches!(result, Err(SienaError::Exists(id)) if id == to));
+    }
+
+    assert_eq!(store.clone().collection("posts").get_all().len(), 3);
+    assert_eq!(store.clone().collection("navigation").get_all().len(), 2);
+
+    let result = store.transaction(|tx| tx.collection("posts").when_is("id", "b").rename("a"));
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "a"));
+
+    // Commits check the records on disk as well
+    let provider = LocalProvider::new(&directory);
+
+    for (collection, from, to) in [("posts", "b", "c"), ("navigation", "blog", "home")] {
+        let record = provider
+            .retrieve(collection)
+            .into_iter()
+            .find(|r| r.id == from)
+            .unwrap();
+        let result = provider.commit(Vec::from([Change::Rename(record, to.to_string())]));
+
+        assert!(matches!(result, Err(SienaError::Exists(id)) if id == to));
+    }
+
+    assert_eq!(provider.retrieve("posts").len(), 3);
+
+    let provider = MemoryProvider::new()
+        .with_record("posts", "a", Vec::new())
+        .with_record("posts", "b", Vec::new());
+    let result = siena(provider.clone())
+        .collection("posts")
+        .when_is("id", "a")
+        .rename("b");
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "b"));
+    assert_eq!(provider.retrieve("posts").len(), 2);
+}
+
+#[test]
+fn atomic_write_test() {
+    let directory = temp_store(
+        "atomic-write",
+        &[(
+            "posts/a.yml",
+            "title: A very long title that gets shortened\n",
+        )],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    store
+        .clone()
+        .collection("posts")
+        .set(Vec::from([("title", &RecordData::Str(String::from("A")))]))
+        .unwrap();
+
+    let files: Vec<String> = fs::read_dir(format!("{}/posts", directory))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+
+    assert_eq!(files, Vec::from(["a.yml"]));
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "title: A\n"
+    );
+}
+
+#[test]
+fn lock_test() {
+    let directory = temp_store("lock", &[("posts/a.yml", "title: a\n")]);
+    let store = siena(LocalProvider::new(&directory).with_lock_wait(LockWait::NoWait));
+    let lock_path = format!("{}/.locks/posts.lock", directory);
+
+    fs::create_dir_all(format!("{}/.locks", directory)).unwrap();
+
+    let held = fs::File::create(&lock_path).unwrap();
+
+    held.lock().unwrap();
+
+    let result = store
+        .clone()
+        .collection("posts")
+        .set(Vec::from([("title", &RecordData::Str(String::from("b")))]));
+
+    assert!(matches!(result, Err(SienaError::LockTimeout(path)) if path == lock_path));
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "title: a\n"
+    );
+
+    drop(held);
+
+    store
+        .collection("posts")
+        .set(Vec::from([("title", &RecordData::Str(String::from("b")))]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "title: b\n"
+    );
+}
+
+#[test]
+fn set_if_unchanged_test() {
+    let directory = temp_store(
+        "set-if-unchanged",
+        &[
+            ("posts/a.yml", "title: a\n"),
+            ("navigation.yml", "- id: home\n  title: Home\n"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    for collection in ["posts", "navigation"] {
+        let read = store.clone().collection(collection).get_first().unwrap();
+
+        assert!(read.version.is_some());
+
+        // Another editor changes the record in the meantime
+        let updated = store
+            .clone()
+            .collection(collection)
+            .set_if_unchanged(
+                read.version.as_deref(),
+                Vec::from([("title", &RecordData::Str(String::from("b")))]),
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_ne!(updated.version, read.version);
+        assert_eq!(
+            store.clone().collection(collection).get_first().unwrap(),
+            updated
+        );
+
+        let result = store.clone().collection(collection).set_if_unchanged(
+            read.version.as_deref(),
+            Vec::from([("title", &RecordData::Str(String::from("c")))]),
+        );
+
+        assert!(matches!(result, Err(SienaError::Conflict(id)) if id == read.id));
+        assert_eq!(
+            store
+                .clone()
+                .collection(collection)
+                .get_first()
+                .unwrap()
+                .data
+                .get("title"),
+            Some(&RecordData::Str(String::from("b")))
+        );
+
+        // Versions are checked again when a transaction commits
+        let result = store.transaction(|tx| {
+            tx.clone().collection(collection).set_if_unchanged(
+                updated.version.as_deref(),
+                Vec::from([("title", &RecordData::Str(String::from("d")))]),
+            )?;
+
+            store
+                .clone()
+                .collection(collection)
+                .set(Vec::from([("title", &RecordData::Str(String::from("e")))]))?;
+
+            Ok(())
+        });
+
+        assert!(matches!(result, Err(SienaError::Conflict(id)) if id == read.id));
+        assert_eq!(
+            store
+                .clone()
+                .collection(collection)
+                .get_first()
+                .unwrap()
+                .data
+                .get("title"),
+            Some(&RecordData::Str(String::from("e")))
+        );
+    }
+}
+
+#[test]
+fn version_test() {
+    // Versions are compared with ones stored by earlier releases, so they
+    // must not depend on the build.
+    assert_eq!(
+        local::version("title: Hello"),
+        "9bdfc2007c6961ef765ed0fe56d48b8790c9550a83c660eb05e9220c30aeeeab"
+    );
+}
+
+#[test]
+fn transaction_test() {
+    let directory = temp_store(
+        "transaction",
+        &[
+            ("posts/a.yml", "title: a\n"),
+            ("posts/b.yml", "title: b\n"),
+            ("navigation.yml", "- id: home\n  title: Home\n"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    let count = store
+        .transaction(|tx| {
+            tx.clone()
+                .collection("posts")
+                .when_is("id", "a")
+                .set(Vec::from([("title", &RecordData::Str(String::from("A")))]))?;
+            tx.clone()
+                .create("posts", "c")
+                .set(Vec::from([("title", &RecordData::Str(String::from("c")))]))?;
+            tx.clone().collection("posts").when_is("id", "b").delete()?;
+            tx.clone().create("navigation", "blog").set(Vec::from([(
+                "title",
+                &RecordData::Str(String::from("Blog")),
+            )]))?;
+
+            // Nothing is written until the transaction is committed, but
+            // queries within it see the staged changes
+            assert_eq!(
+                fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+                "title: a\n"
+            );
+            assert!(Path::new(&format!("{}/posts/b.yml", directory)).exists());
+
+            Ok(tx.collection("posts").get_all().len())
+        })
+        .unwrap();
+
+    let ids = |collection: &str| {
+        let mut ids: Vec<String> = store
+            .clone()
+            .collection(collection)
+            .get_all()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(count, 2);
+    assert_eq!(ids("posts"), Vec::from(["a", "c"]));
+    assert_eq!(ids("navigation"), Vec::from(["blog", "home"]));
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "title: A\n"
+    );
+    assert_eq!(
+        fs::read_dir(format!("{}/.journal", directory))
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn transaction_rollback_test() {
+    let directory = temp_store(
+        "transaction-rollback",
+        &[("posts/a.yml", "title: a\n"), ("posts/b.yml", "title: b\n")],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    // A failing transaction writes nothing
+    let result = store.transaction(|tx| {
+        tx.clone()
+            .collection("posts")
+            .set(Vec::from([("title", &RecordData::Str(String::from("x")))]))?;
+
+        Err::<(), SienaError>(SienaError::Conflict(String::from("a")))
+    });
+
+    assert!(result.is_err());
+
+    // Neither does a transaction failing halfway through its commit, here
+    // by renaming a record whose file is gone by then
+    let result = store.transaction(|tx| {
+        tx.clone()
+            .collection("posts")
+            .when_is("id", "a")
+            .set(Vec::from([("title", &RecordData::Str(String::from("A")))]))?;
+        tx.clone()
+            .create("posts", "c")
+            .set(Vec::from([("title", &RecordData::Str(String::from("c")))]))?;
+        tx.clone()
+            .collection("posts")
+            .when_is("id", "b")
+            .rename("d")?;
+
+        fs::remove_file(format!("{}/posts/b.yml", directory)).unwrap();
+
+        Ok(())
+    });
+
+    assert!(matches!(result, Err(SienaError::Io(_))));
+
+    let files: Vec<String> = fs::read_dir(format!("{}/posts", directory))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+
+    assert_eq!(files, Vec::from(["a.yml"]));
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "title: a\n"
+    );
+}
+
+#[test]
+fn transaction_recover_test() {
+    let directory = temp_store(
+        "transaction-recover",
+        &[
+            ("posts/.a.yml.bak", "title: a\n"),
+            ("posts/a.yml", "title: A\n"),
+        ],
+    );
+    let posts = Path::new(&directory).join("posts");
+    let journal = serde_json::json!({
+        "collections": ["posts"],
+        "moves": [[posts.join("a.yml"), posts.join(".a.yml.bak")], [posts.join(".a.yml.tmp"), posts.join("a.yml")]],
+        "backups": [posts.join(".a.yml.bak")],
+        "staged": [posts.join(".a.yml.tmp")],
+        "committed": false,
+    });
+
+    // A commit that crashed after moving the new contents in place
+    fs::create_dir_all(format!("{}/.journal", directory)).unwrap();
+    fs::write(
+        format!("{}/.journal/commit.json", directory),
+        journal.to_string(),
+    )
+    .unwrap();
+
+    LocalProvider::new(&directory).recover().unwrap();
+
+    let files: Vec<String> = fs::read_dir(&posts)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+
+    assert_eq!(files, Vec::from(["a.yml"]));
+    assert_eq!(
+        fs::read_to_string(posts.join("a.yml")).unwrap(),
+        "title: a\n"
+    );
+    assert!(!Path::new(&format!("{}/.journal/commit.json", directory)).exists());
+}
+
+#[test]
+fn preserve_formatting_test() {
+    let directory = temp_store(
+        "preserve-formatting",
+        &[
+            (
+                "posts/a.yml",
+                "# A post\ntitle: a # short\n\ntags:\n- x\nstatus: draft\n",
+            ),
+            (
+                "posts/b.md",
+                "---\n# B\ntitle: b\nstatus: draft\n---\n\nHi.",
+            ),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    store
+        .clone()
+        .collection("posts")
+        .when_is("id", "a")
+        .set(Vec::from([(
+            "status",
+            &RecordData::Str(String::from("published")),
+        )]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.yml", directory)).unwrap(),
+        "# A post\ntitle: a # short\n\ntags:\n- x\nstatus: published\n"
+    );
+
+    store
+        .collection("posts")
+        .when_is("id", "b")
+        .set(Vec::from([("title", &RecordData::Str(String::from("B")))]))
+        .unwrap();
+
+    let contents = fs::read_to_string(format!("{}/posts/b.md", directory)).unwrap();
+
+    assert!(contents.starts_with("---\n# B\ntitle: B\nstatus: draft\n"));
+}
+
+#[test]
+fn key_order_test() {
+    let directory = temp_store(
+        "key-order",
+        &[
+            ("posts/a.yml", "zeta: 1\nalpha: 2\nmid:\n  z: 1\n  a: 2\n"),
+            (
+                "posts/b.json",
+                "{\"zeta\": 1, \"alpha\": 2, \"mid\": {\"z\": 1, \"a\": 2}}",
+            ),
+            (
+                "posts/c.toml",
+                "zeta = 1\nalpha = 2\n\n[mid]\nz = 1\na = 2\n",
+            ),
+            (
+                "posts/d.md",
+                "---\nzeta: 1\nalpha: 2\nmid:\n  z: 1\n  a: 2\n---\n\nHi.",
+            ),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory));
+
+    for record in store.clone().collection("posts").get_all() {
+        let keys: Vec<&str> = record.data.keys().map(|key| key.as_str()).take(3).collect();
+
+        assert_eq!(keys, Vec::from(["zeta", "alpha", "mid"]), "{}", record.id);
+
+        match record.data.get("mid") {
+            Some(RecordData::Map(mid)) => {
+                assert_eq!(mid.keys().collect::<Vec<&String>>(), Vec::from(["z", "a"]))
+            }
+            _ => panic!("{} has no mid", record.id),
+        }
+    }
+
+    store
+        .create("posts", "e")
+        .set(Vec::from([
+            ("zeta", &RecordData::Num(1)),
+            ("alpha", &RecordData::Num(2)),
+        ]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/e.yml", directory)).unwrap(),
+        "zeta: 1\nalpha: 2\n"
+    );
+}
+
+#[test]
+fn markdown_body_test() {
+    let directory = temp_store(
+        "markdown-body",
+        &[("posts/a.md", "---\ntitle: A\n---\n\nHello from A.")],
+    );
+    let provider = LocalProvider::new(&directory);
+    let store = siena(provider.clone());
+
+    // Records without a body keep the one the file has, or get an empty one
+    provider
+        .set(
+            Vec::from([
+                Record {
+                    id: String::from("a"),
+                    collection: String::from("posts"),
+                    file_name: String::from("a.md"),
+                    ..Default::default()
+                },
+                Record {
+                    id: String::from("b"),
+                    collection: String::from("posts"),
+                    file_name: String::from("b.md"),
+                    ..Default::default()
+                },
+            ]),
+            Vec::from([("title", &RecordData::Str(String::from("New")))]),
+        )
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.md", directory)).unwrap(),
+        "---\ntitle: New\n---\n\nHello from A."
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/b.md", directory)).unwrap(),
+        "---\ntitle: New\n---\n\n"
+    );
+
+    store
+        .clone()
+        .collection("posts")
+        .when_is("id", "a")
+        .set_body("Bye from A.")
+        .unwrap();
+
+    let record = store
+        .collection("posts")
+        .when_is("id", "a")
+        .get_first()
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/a.md", directory)).unwrap(),
+        "---\ntitle: New\n---\n\nBye from A."
+    );
+    assert_eq!(
+        record.data.get("content"),
+        Some(&RecordData::Str(String::from("<p>Bye from A.</p>\n")))
+    );
+}
+
+#[test]
+fn create_format_test() {
+    let directory = temp_store("create-format", &[]);
+    let store = siena(LocalProvider::new(&directory)).format("posts", RecordFormat::Markdown);
+    let title = RecordData::Str(String::from("Hello"));
+
+    store
+        .clone()
+        .create("posts", "hello")
+        .set(Vec::from([("title", &title)]))
+        .unwrap();
+    store
+        .clone()
+        .create("pages", "about")
+        .set(Vec::from([("title", &title)]))
+        .unwrap();
+    store
+        .clone()
+        .create_as("pages", "contact", RecordFormat::Json)
+        .set(Vec::from([("title", &title)]))
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/hello.md", directory)).unwrap(),
+        "---\ntitle: Hello\n---\n\n"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/pages/about.yml", directory)).unwrap(),
+        "title: Hello\n"
+    );
+    assert!(fs::metadata(format!("{}/pages/contact.json", directory)).is_ok());
+
+    let post = store.collection("posts").get_first().unwrap();
+
+    assert_eq!(
+        post.data.get("content_raw"),
+        Some(&RecordData::Str(String::new()))
+    );
+}
+
+#[test]
+fn create_existing_test() {
+    let provider = MemoryProvider::new().with_record(
+        "posts",
+        "hello",
+        Vec::from([("title", RecordData::Str(String::from("Hello")))]),
+    );
+    let store = siena(provider.clone());
+    let title = RecordData::Str(String::from("Hi"));
+
+    let result = store
+        .clone()
+        .create("posts", "hello")
+        .set(Vec::from([("title", &title)]));
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "hello"));
+    assert_eq!(
+        provider.retrieve("posts")[0].data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+
+    store
+        .clone()
+        .upsert("posts", "hello")
+        .set(Vec::from([("title", &title)]))
+        .unwrap();
+
+    assert_eq!(
+        provider.retrieve("posts")[0].data.get("title"),
+        Some(&title)
+    );
+
+    // Records staged in a transaction count as existing too
+    let result = store.transaction(|tx| {
+        tx.clone()
+            .create("posts", "bye")
+            .set(Vec::from([("title", &title)]))?;
+        tx.create("posts", "bye")
+            .set(Vec::from([("title", &title)]))
+    });
+
+    assert!(matches!(result, Err(SienaError::Exists(id)) if id == "bye"));
+    assert_eq!(provider.retrieve("posts").len(), 1);
+}
+
+#[test]
+fn set_new_test() {
+    let directory = temp_store(
+        "set-new",
+        &[
+            ("posts/hello.md", "---\ntitle: Hello\n---\n"),
+            ("navigation.yml", "- id: home\n  title: Home\n"),
+        ],
+    );
+    let provider = LocalProvider::new(&directory);
+    let title = RecordData::Str(String::from("Hi"));
+    let record = |collection: &str, id: &str| Record {
+        id: id.to_string(),
+        collection: collection.to_string(),
+        file_name: format!("{}.yml", id),
+        path: format!("{}.yml", id),
+        ..Default::default()
+    };
+
+    // Another writer created the record since it was checked, here in
+    // another format
+    for (collection, id, file) in [
+        ("posts", "hello", "posts/hello.md"),
+        ("navigation", "home", "navigation.yml"),
+    ] {
+        let before = fs::read_to_string(format!("{}/{}", directory, file)).unwrap();
+        let result = provider.set_new(
+            Vec::from([record(collection, id)]),
+            Vec::from([("title", &title)]),
+        );
+
+        assert!(matches!(result, Err(SienaError::Exists(i)) if i == id));
+        assert_eq!(
+            fs::read_to_string(format!("{}/{}", directory, file)).unwrap(),
+            before
+        );
+    }
+
+    provider
+        .set_new(
+            Vec::from([record("posts", "bye")]),
+            Vec::from([("title", &title)]),
+        )
+        .unwrap();
+
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/bye.yml", directory)).unwrap(),
+        "title: Hi\n"
+    );
+}
+
+// A provider reading before the records another writer just created show
+// up, as when both create records at the same time.
+struct StaleProvider(MemoryProvider);
+
+impl StoreProvider for StaleProvider {
+    fn retrieve(&self, _name: &str) -> Vec<Record> {
+        Vec::new()
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.0.set(records, data)
+    }
+
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
+        self.0.delete(records)
+    }
+
+    fn set_new(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        self.0.set_new(records, data)
+    }
+}
+
+#[test]
+fn insert_race_test() {
+    let provider = MemoryProvider::new().with_record(
+        "posts",
+        "hello",
+        Vec::from([("title", RecordData::Str(String::from("Hello")))]),
+    );
+    let store = siena(StaleProvider(provider.clone()))
+        .id_strategy("posts", IdStrategy::Slug(String::from("title")));
+
+    let post = store
+        .insert(
+            "posts",
+            Vec::from([("title", &RecordData::Str(String::from("Hello")))]),
+        )
+        .unwrap();
+
+    // The id taken in the meantime is skipped
+    assert_eq!(post.id, "hello-2");
+    assert_eq!(
+        provider.retrieve("posts")[0].data.get("title"),
+        Some(&RecordData::Str(String::from("Hello")))
+    );
+}
+
+#[test]
+fn insert_test() {
+    let directory = temp_store(
+        "insert",
+        &[
+            ("posts/hello.md", "---\ntitle: Hello\n---\n"),
+            ("notes/7.yml", "text: Seven\n"),
+        ],
+    );
+    let store = siena(LocalProvider::new(&directory))
+        .format("posts", RecordFormat::Markdown)
+        .id_strategy("posts", IdStrategy::Slug(String::from("title")))
+        .id_strategy("notes", IdStrategy::Increment);
+    let title = RecordData::Str(String::from("Hello"));
+
+    let post = store
+        .clone()
+        .insert("posts", Vec::from([("title", &title)]))
+        .unwrap();
+
+    assert_eq!(post.id, "hello-2");
+    assert_eq!(post.file_name, "hello-2.md");
+    assert_eq!(
+        fs::read_to_string(format!("{}/posts/hello-2.md", directory)).unwrap(),
+        "---\ntitle: Hello\n---\n\n"
+    );
+
+    let note = store
+        .clone()
+        .insert("notes", Vec::from([("text", &title)]))
+        .unwrap();
+
+    assert_eq!(note.id, "8");
+    assert!(fs::metadata(format!("{}/notes/8.yml", directory)).is_ok());
+
+    let result = store.clone().insert("posts", Vec::new());
+
+    assert!(matches!(result, Err(SienaError::NoId(collection)) if collection == "posts"));
+
+    // Records staged in a transaction are taken into account
+    let ids = store
+        .transaction(|tx| {
+            let first = tx.clone().insert("notes", Vec::from([("text", &title)]))?;
+            let second = tx.insert("notes", Vec::from([("text", &title)]))?;
+
+            Ok(Vec::from([first.id, second.id]))
+        })
+        .unwrap();
+
+    assert_eq!(ids, Vec::from(["9", "10"]));
+}
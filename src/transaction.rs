@@ -0,0 +1,163 @@
+use crate::siena::{Record, RecordData, SienaError, StoreProvider};
+use std::sync::{Arc, Mutex};
+
+// A change staged by a transaction, to be committed together with the
+// other changes of the transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    // Write the record, with its data already updated.
+    Set(Record),
+    // Write the record, with its data already updated, only if it's still
+    // at its version.
+    SetIfUnchanged(Record),
+    Delete(Record),
+    // Give the record the new id.
+    Rename(Record, String),
+}
+
+impl Change {
+    // Apply the change to the `records` of a collection, as they'd be once
+    // it is committed.
+    pub(crate) fn apply(&self, records: &mut Vec<Record>) {
+        match self {
+            Change::Set(record) | Change::SetIfUnchanged(record) => {
+                match records.iter_mut().find(|r| r.id == record.id) {
+                    Some(existing) => *existing = record.clone(),
+                    None => records.push(record.clone()),
+                }
+            }
+            Change::Delete(record) => records.retain(|r| r.id != record.id),
+            Change::Rename(record, id) => {
+                records.retain(|r| r.id != record.id);
+                records.push(record.renamed(id));
+            }
+        }
+    }
+
+    // The record the change is about.
+    pub fn record(&self) -> &Record {
+        match self {
+            Change::Set(record)
+            | Change::SetIfUnchanged(record)
+            | Change::Delete(record)
+            | Change::Rename(record, _) => record,
+        }
+    }
+}
+
+// A provider staging the writes of a transaction instead of persisting
+// them, while reading through to the `provider` with the staged changes
+// applied on top.
+pub(crate) struct Transaction {
+    provider: Arc<dyn StoreProvider>,
+    changes: Mutex<Vec<Change>>,
+}
+
+impl Transaction {
+    pub(crate) fn new(provider: Arc<dyn StoreProvider>) -> Transaction {
+        Transaction {
+            provider,
+            changes: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn stage(&self, change: Change) {
+        self.changes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(change);
+    }
+
+    // The changes staged so far, in order.
+    pub(crate) fn changes(&self) -> Vec<Change> {
+        self.changes
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+impl StoreProvider for Transaction {
+    fn retrieve(&self, name: &str) -> Vec<Record> {
+        let mut records = self.provider.retrieve(name);
+
+        for change in self.changes() {
+            if change.record().collection == name {
+                change.apply(&mut records);
+            }
+        }
+
+        records
+    }
+
+    fn set(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        let mut updated_records: Vec<Record> = Vec::new();
+
+        for mut record in records {
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            self.stage(Change::Set(record.clone()));
+            updated_records.push(record);
+        }
+
+        Ok(updated_records)
+    }
+
+    // Versions are checked against the records as staged so far, and again
+    // by the provider when committing.
+    fn set_if_unchanged(
+        &self,
+        records: Vec<Record>,
+        data: Vec<(&str, &RecordData)>,
+    ) -> Result<Vec<Record>, SienaError> {
+        for record in &records {
+            let current = self
+                .retrieve(&record.collection)
+                .into_iter()
+                .find(|r| r.id == record.id)
+                .and_then(|r| r.version);
+
+            if current != record.version {
+                return Err(SienaError::Conflict(record.id.clone()));
+            }
+        }
+
+        let mut updated_records: Vec<Record> = Vec::new();
+
+        for mut record in records {
+            for (key, value) in &data {
+                record.data.insert(key.to_string(), (*value).clone());
+            }
+
+            self.stage(Change::SetIfUnchanged(record.clone()));
+            updated_records.push(record);
+        }
+
+        Ok(updated_records)
+    }
+
+    fn delete(&self, records: Vec<Record>) -> Result<(), SienaError> {
+        for record in records {
+            self.stage(Change::Delete(record));
+        }
+
+        Ok(())
+    }
+
+    fn rename(&self, record: Record, id: &str) -> Result<Record, SienaError> {
+        if self.retrieve(&record.collection).iter().any(|r| r.id == id) {
+            return Err(SienaError::Exists(id.to_string()));
+        }
+
+        let renamed = record.renamed(id);
+
+        self.stage(Change::Rename(record, id.to_string()));
+        Ok(renamed)
+    }
+}
@@ -1,13 +1,118 @@
+use crate::siena::{RecordData, RecordMap};
+
 pub fn str_ends_with_any(s: &str, suffixes: Vec<&str>) -> bool {
-    return suffixes.iter().any(|&suffix| s.ends_with(suffix));
+    suffixes.iter().any(|&suffix| s.ends_with(suffix))
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+// TOML has no `null`, so leave out keys and array items that are null.
+pub fn without_nulls(data: &RecordMap) -> RecordMap {
+    fn strip(value: &RecordData) -> Option<RecordData> {
+        match value {
+            RecordData::Null => None,
+            RecordData::Map(map) => Some(RecordData::Map(without_nulls(map))),
+            RecordData::Vec(items) => {
+                Some(RecordData::Vec(items.iter().filter_map(strip).collect()))
+            }
+            value => Some(value.clone()),
+        }
+    }
+
+    data.iter()
+        .filter_map(|(key, value)| Some((key.clone(), strip(value)?)))
+        .collect()
+}
+
+// Parse TOML into record data. `RecordData` has no type for dates and
+// times, so TOML datetimes are read as strings.
+pub fn from_toml(contents: &str) -> Result<RecordMap, toml::de::Error> {
+    fn strip(value: toml::Value) -> toml::Value {
+        match value {
+            toml::Value::Datetime(datetime) => toml::Value::String(datetime.to_string()),
+            toml::Value::Array(items) => toml::Value::Array(items.into_iter().map(strip).collect()),
+            toml::Value::Table(table) => toml::Value::Table(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, strip(value)))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    strip(toml::Value::Table(toml::from_str(contents)?)).try_into()
+}
+
+// Write record data as TOML, leaving out nulls, and writing strings that
+// hold a TOML datetime, like the ones `from_toml` reads, as datetimes.
+pub fn to_toml(data: &RecordMap) -> Result<String, toml::ser::Error> {
+    fn restore(value: toml::Value) -> toml::Value {
+        match value {
+            toml::Value::String(string) => match string.parse() {
+                Ok(datetime) => toml::Value::Datetime(datetime),
+                Err(_) => toml::Value::String(string),
+            },
+            toml::Value::Array(items) => {
+                toml::Value::Array(items.into_iter().map(restore).collect())
+            }
+            toml::Value::Table(table) => toml::Value::Table(
+                table
+                    .into_iter()
+                    .map(|(key, value)| (key, restore(value)))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    toml::to_string(&restore(toml::Value::try_from(without_nulls(data))?))
 }
 
 #[test]
 fn string_ends_with_any_test() {
-    assert_eq!(str_ends_with_any("o.yml", Vec::from(["yml"])), true);
-    assert_eq!(str_ends_with_any("o.yml", Vec::from(["md", "yml"])), true);
-    assert_eq!(str_ends_with_any("o.yml2", Vec::from(["md", "yml"])), false);
-    assert_eq!(str_ends_with_any("o.yml2", Vec::from(["md"])), false);
-    assert_eq!(str_ends_with_any("o.yml2", Vec::from(["yml"])), false);
-    assert_eq!(str_ends_with_any("o.yml2", Vec::from(["yml2"])), true);
+    assert!(str_ends_with_any("o.yml", Vec::from(["yml"])));
+    assert!(str_ends_with_any("o.yml", Vec::from(["md", "yml"])));
+    assert!(!str_ends_with_any("o.yml2", Vec::from(["md", "yml"])));
+    assert!(!str_ends_with_any("o.yml2", Vec::from(["md"])));
+    assert!(!str_ends_with_any("o.yml2", Vec::from(["yml"])));
+    assert!(str_ends_with_any("o.yml2", Vec::from(["yml2"])));
+}
+
+#[test]
+fn hex_test() {
+    assert_eq!(hex_encode(b"Hi!"), "486921");
+    assert_eq!(hex_decode("486921"), Some(b"Hi!".to_vec()));
+    assert_eq!(hex_decode(&hex_encode(b"")), Some(Vec::new()));
+    assert_eq!(hex_decode("48692"), None);
+    assert_eq!(hex_decode("zz"), None);
+}
+
+#[test]
+fn toml_datetime_test() {
+    let contents = "title = \"Hello\"\ndate = 2024-01-05T10:00:00Z\nday = 2024-01-05\n\n[event]\nat = 10:30:00\n";
+    let data = from_toml(contents).unwrap();
+
+    assert_eq!(
+        data.get("date"),
+        Some(&RecordData::Str(String::from("2024-01-05T10:00:00Z")))
+    );
+    assert_eq!(
+        data.get("day"),
+        Some(&RecordData::Str(String::from("2024-01-05")))
+    );
+    assert_eq!(to_toml(&data).unwrap(), contents);
 }
@@ -0,0 +1,253 @@
+use crate::siena::{RecordData, RecordMap};
+
+// A top-level entry of a YAML mapping, with the lines holding it: from the
+// line of its key up to, but not including, `end`.
+struct Entry {
+    key: String,
+    start: usize,
+    end: usize,
+}
+
+// The key of the top-level mapping entry on `line`, if it holds one.
+fn entry_key(line: &str) -> Option<String> {
+    let first = line.chars().next()?;
+
+    let (key, rest) = match first {
+        '"' | '\'' => {
+            let close = line[1..].find(first)? + 1;
+            let key = &line[1..close];
+
+            // Leave escaped keys alone
+            if key.contains('\\') || line[close + 1..].starts_with(first) {
+                return None;
+            }
+
+            (key.to_string(), &line[close + 1..])
+        }
+        c if c.is_whitespace() || "#-?:{[&*!|>%@`,".contains(c) => return None,
+        _ => {
+            let colon = match line.find(": ") {
+                Some(colon) => colon,
+                None => line.trim_end().strip_suffix(':')?.len(),
+            };
+
+            (line[..colon].trim_end().to_string(), &line[colon..])
+        }
+    };
+
+    rest.starts_with(':').then_some(key)
+}
+
+// The top-level entries of the block mapping in `lines`, or `None` if the
+// lines hold anything else.
+fn entries(lines: &[&str]) -> Option<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Directives and the document marker may come before the mapping
+        if entries.is_empty() && (line.starts_with('%') || line.trim_end() == "---") {
+            continue;
+        }
+
+        // Indented lines, and sequence items, belong to the value of the
+        // entry before them
+        if line.starts_with(char::is_whitespace)
+            || (line.starts_with('-') && !line.starts_with("---"))
+        {
+            entries.last_mut()?.end = i + 1;
+            continue;
+        }
+
+        entries.push(Entry {
+            key: entry_key(line)?,
+            start: i,
+            end: i + 1,
+        });
+    }
+
+    Some(entries)
+}
+
+// The comment at the end of `line`, if it has one.
+fn trailing_comment(line: &str) -> Option<&str> {
+    let mut quote: Option<char> = None;
+    let mut previous = ' ';
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') if previous.is_whitespace() => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '#') if previous.is_whitespace() && i > 0 => {
+                return Some(line[i..].trim_end());
+            }
+            _ => {}
+        }
+
+        previous = c;
+    }
+
+    None
+}
+
+// The entry for `key` holding `value`, with lines ending in `newline`,
+// keeping the comment that trailed its `previous` lines when both fit on a
+// single line.
+fn render(key: &str, value: &RecordData, previous: &[&str], newline: &str) -> Option<String> {
+    let rendered =
+        serde_yaml::to_string(&RecordMap::from([(key.to_string(), value.clone())])).ok()?;
+
+    if let ([line], 1) = (previous, rendered.lines().count()) {
+        if let Some(comment) = trailing_comment(line) {
+            return Some(format!("{} {}{}", rendered.trim_end(), comment, newline));
+        }
+    }
+
+    Some(rendered.replace('\n', newline))
+}
+
+// Update the YAML mapping in `source` to hold `data`, rewriting only the
+// top-level entries whose values changed, removing the ones `data` lacks
+// and adding its new keys at the end. Everything else, comments and key
+// order included, is kept as it is. Returns `None` when `source` isn't a
+// block mapping that can be edited in place.
+pub fn update(source: &str, data: &RecordMap) -> Option<String> {
+    let current: RecordMap = match source.trim().is_empty() {
+        true => RecordMap::new(),
+        false => serde_yaml::from_str(source).ok()?,
+    };
+
+    // Merge keys make the values depend on each other
+    if source
+        .lines()
+        .any(|line| line.trim_start().starts_with("<<"))
+    {
+        return None;
+    }
+
+    let newline = match source.contains("\r\n") {
+        true => "\r\n",
+        false => "\n",
+    };
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+    let entries = entries(&lines)?;
+
+    if entries.len() != current.len()
+        || entries
+            .iter()
+            .any(|entry| !current.contains_key(&entry.key))
+    {
+        return None;
+    }
+
+    let mut output = String::new();
+    let mut next = 0;
+
+    for entry in &entries {
+        output.extend(lines[next..entry.start].iter().copied());
+        next = entry.end;
+
+        let previous = &lines[entry.start..entry.end];
+
+        match data.get(&entry.key) {
+            None => {}
+            Some(value) if current.get(&entry.key) == Some(value) => {
+                output.extend(previous.iter().copied())
+            }
+            Some(value) => output.push_str(&render(&entry.key, value, previous, newline)?),
+        }
+    }
+
+    output.extend(lines[next..].iter().copied());
+
+    for (key, value) in data.iter().filter(|(key, _)| !current.contains_key(*key)) {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push_str(newline);
+        }
+
+        output.push_str(&render(key, value, &[], newline)?);
+    }
+
+    Some(output)
+}
+
+#[test]
+fn update_test() {
+    let source = "# Post\ntitle: Hello # the title\n\n# Tags\ntags:\n- a\n- b\nbody: |\n  Line\n\n  # Not a comment\ndate: 2023-10-20\n";
+    let mut data: RecordMap = serde_yaml::from_str(source).unwrap();
+
+    assert_eq!(update(source, &data), Some(source.to_string()));
+
+    data.insert(String::from("title"), RecordData::Str(String::from("Bye")));
+    data.insert(
+        String::from("tags"),
+        RecordData::Vec(Vec::from([RecordData::Str(String::from("c"))])),
+    );
+    data.insert(String::from("draft"), RecordData::Bool(true));
+    data.shift_remove("date");
+
+    assert_eq!(
+        update(source, &data),
+        Some(String::from(
+            "# Post\ntitle: Bye # the title\n\n# Tags\ntags:\n- c\nbody: |\n  Line\n\n  # Not a comment\ndraft: true\n"
+        ))
+    );
+}
+
+#[test]
+fn update_unsupported_test() {
+    let data = RecordMap::from([(String::from("a"), RecordData::Num(1))]);
+
+    assert_eq!(update("- a\n- b\n", &data), None);
+    assert_eq!(update("{a: 1, b: 2}\n", &data), None);
+    assert_eq!(
+        update("base: &base\n  a: 1\nother:\n  <<: *base\n", &data),
+        None
+    );
+    assert_eq!(update("", &data), Some(String::from("a: 1\n")));
+}
+
+#[test]
+fn update_document_marker_test() {
+    let source = "%YAML 1.2\n---\n# Post\ntitle: Hello\n";
+    let mut data: RecordMap = serde_yaml::from_str(source).unwrap();
+
+    data.insert(String::from("title"), RecordData::Str(String::from("Bye")));
+
+    assert_eq!(
+        update(source, &data),
+        Some(String::from("%YAML 1.2\n---\n# Post\ntitle: Bye\n"))
+    );
+    assert_eq!(
+        update("---\ntitle: Hello # the title\n", &data),
+        Some(String::from("---\ntitle: Bye # the title\n"))
+    );
+}
+
+#[test]
+fn update_crlf_test() {
+    let source = "# Post\r\ntitle: Hello # the title\r\ntags:\r\n- a\r\n";
+    let mut data: RecordMap = serde_yaml::from_str(source).unwrap();
+
+    data.insert(String::from("title"), RecordData::Str(String::from("Bye")));
+    data.insert(
+        String::from("tags"),
+        RecordData::Vec(Vec::from([
+            RecordData::Str(String::from("b")),
+            RecordData::Str(String::from("c")),
+        ])),
+    );
+    data.insert(String::from("draft"), RecordData::Bool(true));
+
+    assert_eq!(
+        update(source, &data),
+        Some(String::from(
+            "# Post\r\ntitle: Bye # the title\r\ntags:\r\n- b\r\n- c\r\ndraft: true\r\n"
+        ))
+    );
+}